@@ -1,109 +1,81 @@
-/// We derive Deserialize/Serialize so we can persist app state on shutdown.
-#[derive(serde::Deserialize, serde::Serialize)]
-#[serde(default)] // if we add new fields, give them default values when deserializing old state
-pub struct TemplateApp {
-    // Example stuff:
-    label: String,
-
-    #[serde(skip)] // This how you opt-out of serialization of a field
-    value: f32,
-}
-
-impl Default for TemplateApp {
-    fn default() -> Self {
-        Self {
-            // Example stuff:
-            label: "Hello World!".to_owned(),
-            value: 2.7,
-        }
-    }
+// the eframe app, owns the clock thread and the channels between it and the gui
+
+use crate::clock_runner::run_clock;
+use crate::engine_clock::SystemClock;
+use crate::gui_runner::Gui;
+use crate::messages::*;
+use crate::midi_backend::MidirBackend;
+use crate::port_watcher::run_port_watcher;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+pub struct RhoApp {
+    gui: Gui,
+    running: Arc<AtomicBool>,
+    clock_handle: Option<JoinHandle<()>>,
+    port_watcher_handle: Option<JoinHandle<()>>,
 }
 
-impl TemplateApp {
+impl RhoApp {
     /// Called once before the first frame.
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
-        // This is also where you can customize the look and feel of egui using
-        // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
+        let (tx_to_gui, rx_from_clock) = channel::<MessageToGui>();
+        let (tx_to_clock, rx_from_gui) = channel::<MessageGuiToRho>();
+
+        let running = Arc::new(AtomicBool::new(true));
+        let port_watcher_handle =
+            run_port_watcher(tx_to_gui.clone(), tx_to_clock.clone(), running.clone());
+        let clock_handle = run_clock(
+            MidirBackend,
+            SystemClock::new(),
+            tx_to_gui,
+            running.clone(),
+            rx_from_gui,
+        );
 
-        // Load previous app state (if any).
-        // Note that you must enable the `persistence` feature for this to work.
-        if let Some(storage) = cc.storage {
-            return eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
+        Self {
+            gui: Gui::new(rx_from_clock, tx_to_clock, cc.storage),
+            running,
+            clock_handle: Some(clock_handle),
+            port_watcher_handle: Some(port_watcher_handle),
         }
-
-        Default::default()
     }
-}
 
-impl eframe::App for TemplateApp {
-    /// Called by the frame work to save state before shutdown.
-    fn save(&mut self, storage: &mut dyn eframe::Storage) {
-        eframe::set_value(storage, eframe::APP_KEY, self);
+    // stop the threads, the clock thread closes the midi connections. safe to call more than once
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(handle) = self.clock_handle.take() {
+            if handle.join().is_err() {
+                log::error!("Clock thread panicked");
+            }
+        }
+        if let Some(handle) = self.port_watcher_handle.take() {
+            if handle.join().is_err() {
+                log::error!("Port watcher thread panicked");
+            }
+        }
     }
+}
 
+impl eframe::App for RhoApp {
     /// Called each time the UI needs repainting, which may be many times per second.
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // Put your widgets into a `SidePanel`, `TopBottomPanel`, `CentralPanel`, `Window` or `Area`.
-        // For inspiration and more examples, go to https://emilk.github.io/egui
-
-        egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
-            // The top panel is often a good place for a menu bar:
-
-            egui::menu::bar(ui, |ui| {
-                // NOTE: no File->Quit on web pages!
-                let is_web = cfg!(target_arch = "wasm32");
-                if !is_web {
-                    ui.menu_button("File", |ui| {
-                        if ui.button("Quit").clicked() {
-                            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
-                        }
-                    });
-                    ui.add_space(16.0);
-                }
-
-                egui::widgets::global_dark_light_mode_buttons(ui);
-            });
-        });
-
-        egui::CentralPanel::default().show(ctx, |ui| {
-            // The central panel the region left after adding TopPanel's and SidePanel's
-            ui.heading("eframe template");
-
-            ui.horizontal(|ui| {
-                ui.label("Write something: ");
-                ui.text_edit_singleline(&mut self.label);
-            });
-
-            ui.add(egui::Slider::new(&mut self.value, 0.0..=10.0).text("value"));
-            if ui.button("Increment").clicked() {
-                self.value += 1.0;
-            }
-
-            ui.separator();
+        self.gui.update(ctx);
+    }
 
-            ui.add(egui::github_link_file!(
-                "https://github.com/emilk/eframe_template/blob/master/",
-                "Source code."
-            ));
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        self.gui.save(storage);
+    }
 
-            ui.with_layout(egui::Layout::bottom_up(egui::Align::LEFT), |ui| {
-                powered_by_egui_and_eframe(ui);
-                egui::warn_if_debug_build(ui);
-            });
-        });
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.stop();
     }
 }
 
-fn powered_by_egui_and_eframe(ui: &mut egui::Ui) {
-    ui.horizontal(|ui| {
-        ui.spacing_mut().item_spacing.x = 0.0;
-        ui.label("Powered by ");
-        ui.hyperlink_to("egui", "https://github.com/emilk/egui");
-        ui.label(" and ");
-        ui.hyperlink_to(
-            "eframe",
-            "https://github.com/emilk/egui/tree/master/crates/eframe",
-        );
-        ui.label(".");
-    });
+impl Drop for RhoApp {
+    fn drop(&mut self) {
+        self.stop();
+    }
 }
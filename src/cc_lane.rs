@@ -0,0 +1,76 @@
+// a lane of controller values for a row, one for each step, so a row can move a synth's
+// cutoff or mod wheel along with its rhythm
+
+// filter cutoff on most synths
+pub const DEFAULT_CC: u8 = 74;
+pub const DEFAULT_CC_VALUE: u8 = 64;
+pub const MAX_CC_VALUE: u8 = 127;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CcLane {
+    pub enabled: bool,
+    pub cc: u8,
+    // None sends on the row's channels
+    pub channel: Option<u8>,
+    // glide from each step's value to the next, rather than jumping at the step
+    pub smoothing: bool,
+    pub values: Vec<u8>,
+}
+
+impl CcLane {
+    pub fn new() -> Self {
+        CcLane {
+            enabled: false,
+            cc: DEFAULT_CC,
+            channel: None,
+            smoothing: false,
+            values: vec![],
+        }
+    }
+
+    // the value part way through a step, from 0 at the start of the step to 1 at the start of
+    // the next. None if the lane has no value for the step
+    pub fn value(&self, step: usize, fraction: f32) -> Option<u8> {
+        let value = *self.values.get(step)? as f32;
+        if !self.smoothing {
+            return Some(value as u8);
+        }
+        let next = self.values[(step + 1) % self.values.len()] as f32;
+        Some((value + (next - value) * fraction).round() as u8)
+    }
+
+    // keep a value for each step of the row, new steps start in the middle
+    pub fn resize(&mut self, num_steps: usize) {
+        self.values.resize(num_steps, DEFAULT_CC_VALUE);
+    }
+}
+
+impl Default for CcLane {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cc_lane_value() {
+        let mut lane = CcLane::new();
+        assert_eq!(lane.value(0, 0.0), None);
+
+        lane.values = vec![0, 100];
+        assert_eq!(lane.value(0, 0.0), Some(0));
+        assert_eq!(lane.value(0, 0.5), Some(0));
+        assert_eq!(lane.value(1, 0.0), Some(100));
+
+        // smoothing glides towards the next step, wrapping around the row
+        lane.smoothing = true;
+        assert_eq!(lane.value(0, 0.5), Some(50));
+        assert_eq!(lane.value(1, 0.25), Some(75));
+
+        lane.resize(3);
+        assert_eq!(lane.values, vec![0, 100, DEFAULT_CC_VALUE]);
+    }
+}
@@ -1,136 +1,467 @@
 // module with the function that runs the clock thread
 
-use crate::clock::Clock;
+use crate::engine_clock::EngineClock;
+use crate::external_clock::{ExternalClock, PULSES_PER_MIDI_BEAT};
 use crate::messages::*;
-use crate::midi_helpers::*;
+use crate::midi_backend::MidiBackend;
+use crate::midi_in::{HeldNotes, MidiIn};
+use crate::midi_learn::{cc_to_tempo, ButtonStates, CcSource, Control, MidiMappings};
+use crate::midi_out::MidiOuts;
+use crate::midi_thru::MidiThru;
 use crate::note_assigner::Note;
-use crate::rho::Rho;
-use crate::rho_config::NUM_ROWS;
-use midir::MidiOutputConnection;
+use crate::port_watcher::MidiPorts;
+use crate::rho::{CcEvent, NoteEvent, Rho};
+use crate::rho_config::{BEATS_PER_BAR, MAX_NUM_ROWS, MIDI_CLOCK_PPQN, MIN_NUM_ROWS};
+use crate::routing::Routing;
+use crate::scheduler::{ClockEvent, Scheduler};
+use std::collections::{BTreeSet, HashSet, VecDeque};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
-pub fn run_clock(
+// how long without a midi clock pulse before the incoming tempo is forgotten
+const EXTERNAL_CLOCK_TIMEOUT: Duration = Duration::from_millis(500);
+
+// the midi connections are made with the backend, which is midir apart from in tests. The clock
+// is the system clock apart from in tests too
+pub fn run_clock<B: MidiBackend, C: EngineClock>(
+    backend: B,
+    mut clock: C,
     tx: std::sync::mpsc::Sender<MessageToGui>,
     running: Arc<AtomicBool>,
-    rx_midi_in: std::sync::mpsc::Receiver<MidiInMessage>,
     rx_gui: std::sync::mpsc::Receiver<MessageGuiToRho>,
 ) -> thread::JoinHandle<()> {
     let mut rho = Rho::new();
 
-    // is this ARC necessary?
-    let clock_arc = Arc::new(Mutex::new(Clock::new()));
-    // this is stupid, it only works if we have audio clock rate to sync to
-    // the clock should just be at whatever the tempo is
-    let sample_rate = 1000.0;
-    let period_ms = (1000.0 / sample_rate) as u64;
+    // the gui sends the real tempo before we start playing
+    let mut scheduler = Scheduler::new(120.0);
+    // the tempo the gui last knew about, so it can follow ramps
+    let mut sent_tempo = scheduler.tempo();
+
+    let mut sent_notes_for_rows: Vec<Vec<Note>> = vec![];
+    let mut sent_playing_steps: Vec<Option<usize>> = vec![];
+    // where each row's notes go
+    let mut routing = Routing::new();
 
-    let mut sent_notes_for_rows: [Vec<Note>; NUM_ROWS] = Default::default();
-    let mut midi_out_channel: u8 = 0;
+    // the midi in connection is made when the gui picks a port, and sends to us on this channel
+    let (tx_midi_in, rx_midi_in) = std::sync::mpsc::channel::<MidiInMessage>();
+    let mut midi_in = MidiIn::new(backend.clone(), tx_midi_in);
+    let mut held_notes = HeldNotes::new();
+    // None is omni
+    let mut midi_in_channel: Option<u8> = None;
 
-    let mut maybe_midi_out_conn: Option<MidiOutputConnection> = None;
+    let mut midi_outs = MidiOuts::new(backend);
+    // the output ports that want midi clock and transport messages
+    let mut clock_out_ports: HashSet<String> = HashSet::new();
+    // clock out ports turned on while playing, they are told where we are and started on the next
+    // sixteenth so they play in step with us
+    let mut waiting_clock_ports: HashSet<String> = HashSet::new();
+    // the ports that were there when the port watcher last looked
+    let mut ports = MidiPorts::default();
 
     let mut is_playing = false;
+    // true until we start playing, or after a rewind. Decides between midi start and continue
+    let mut at_start = true;
+
+    let mut clock_source = ClockSource::Internal;
+    let mut external_clock = ExternalClock::new();
+    let mut last_pulse_time: Option<Duration> = None;
+
+    let mut sent_status: Option<EngineStatus> = None;
 
-    let mut tick_time = Instant::now();
+    // the gui keeps the hold checkbox, we need it so a button can toggle it
+    let mut hold_enabled = false;
+    let mut mappings = MidiMappings::new();
+    // the control the next controller moved is mapped to
+    let mut learning: Option<Control> = None;
+    let mut buttons = ButtonStates::new();
+    // messages from mapped controllers, handled along with the gui's
+    let mut controlled: VecDeque<MessageGuiToRho> = VecDeque::new();
+    let mut midi_thru = MidiThru::new();
 
     // run a clock in another thread.
-    let handle = thread::spawn(move || {
+    thread::spawn(move || {
         while running.load(Ordering::SeqCst) {
-            // wait until the next clock tick
-            while Instant::now() < tick_time {
-                thread::yield_now();
-            }
-
-            // check to see if there are any messages from the midi in
-            match rx_midi_in.try_recv() {
-                Ok(MidiInMessage::NoteOn(note, velocity)) => {
-                    rho.note_on(note.into(), velocity.into());
+            // check to see if there are any messages from the midi in, clock messages can arrive
+            // faster than we poll so take them all
+            while let Ok(midi_in_message) = rx_midi_in.try_recv() {
+                let following = clock_source == ClockSource::ExternalMidi;
+                if passes_thru(&midi_in_message, midi_in_channel, &mappings, learning) {
+                    if let (Some(bytes), Some(port)) = (
+                        midi_thru.thru(&midi_in_message, routing.main_channel),
+                        &routing.main_port,
+                    ) {
+                        midi_outs.send_thru(port, &bytes);
+                    }
                 }
-                Ok(MidiInMessage::NoteOff(note)) => {
-                    rho.note_off(note.into());
+                match midi_in_message {
+                    MidiInMessage::NoteOn(channel, note, velocity) => {
+                        if midi_in_channel.map_or(true, |c| c == channel) {
+                            rho.note_on(note.into(), velocity.into());
+                            held_notes.note_on(channel, note);
+                        }
+                    }
+                    MidiInMessage::NoteOff(channel, note) => {
+                        if midi_in_channel.map_or(true, |c| c == channel) {
+                            rho.note_off(note.into());
+                            held_notes.note_off(channel, note);
+                        }
+                    }
+                    // controllers are mapped on any channel
+                    MidiInMessage::ControlChange(channel, cc, value) => {
+                        let source = CcSource { channel, cc };
+                        if let Some(control) = learning.take() {
+                            mappings.learn(source, control);
+                            let _ = tx.send(MessageToGui::Learned { source, control });
+                        } else if let Some(control) = mappings.control_for(source) {
+                            let controls = ControlState {
+                                pressed: buttons.pressed(source, value),
+                                is_playing,
+                                hold_enabled,
+                                rho: &rho,
+                            };
+                            on_control(control, value, controls, &mut controlled, &tx);
+                        }
+                    }
+                    // only ever passed on
+                    MidiInMessage::PitchBend(..)
+                    | MidiInMessage::ChannelPressure(..)
+                    | MidiInMessage::PolyPressure(..)
+                    | MidiInMessage::ProgramChange(..) => {}
+                    MidiInMessage::Clock(stamp) => {
+                        last_pulse_time = Some(clock.now());
+                        let tick = external_clock.on_pulse(stamp);
+                        if !following {
+                            continue;
+                        }
+                        if let Some(tick) = tick {
+                            on_clock_tick(
+                                tick,
+                                clock.now(),
+                                &mut rho,
+                                &mut midi_outs,
+                                &routing,
+                                &tx,
+                                &mut sent_playing_steps,
+                            );
+                            // once a beat is plenty
+                            if tick % MIDI_CLOCK_PPQN as u64 == 0 {
+                                let _ = tx.send(MessageToGui::ExternalTempo {
+                                    bpm: external_clock.bpm(),
+                                });
+                            }
+                        }
+                    }
+                    MidiInMessage::Start => {
+                        external_clock.start();
+                        if following {
+                            rho.reset();
+                        }
+                    }
+                    MidiInMessage::Continue => external_clock.resume(),
+                    MidiInMessage::Stop => {
+                        external_clock.stop();
+                        if following {
+                            stop_all_notes(&mut rho, &mut midi_outs);
+                            let _ = tx.send(MessageToGui::ExternalTempo { bpm: None });
+                        }
+                    }
+                    MidiInMessage::SongPosition(midi_beats) => {
+                        external_clock.set_song_position(midi_beats);
+                        if following {
+                            rho.set_position(external_clock.position());
+                        }
+                    }
                 }
-                _ => (),
             }
 
-            match rx_gui.try_recv() {
-                Ok(MessageGuiToRho::RowActivations { row_activations }) => {
-                    rho.set_row_activations(row_activations);
-                }
-                Ok(MessageGuiToRho::HoldNotesEnabled { enabled }) => {
-                    rho.set_hold_notes_enabled(enabled);
-                }
-                Ok(MessageGuiToRho::SetMidiOutPort { port }) => {
-                    // open a midi out connection
-                    let midi_out_conn = get_midi_out_connection(port);
-                    maybe_midi_out_conn = match midi_out_conn {
-                        Ok(conn) => Some(conn),
-                        Err(e) => {
-                            eprintln!("Error: {}", e);
-                            return;
-                        }
-                    };
-                }
-                Ok(MessageGuiToRho::SetMidiChannelOut { channel }) => {
-                    midi_out_channel = channel;
-                }
-                Ok(MessageGuiToRho::SetPlaying { playing }) => {
-                    is_playing = playing;
-                }
-                Ok(MessageGuiToRho::SetTempo { tempo }) => {
-                    let mut clock = clock_arc.lock().unwrap();
-                    let rate_hz = tempo / 60.0;
-                    clock.set_rate(rate_hz, sample_rate);
+            // the tempo we measured no longer holds once the pulses stop
+            if last_pulse_time.map_or(false, |time| clock.now() >= time + EXTERNAL_CLOCK_TIMEOUT) {
+                last_pulse_time = None;
+                external_clock.forget_tempo();
+                let _ = tx.send(MessageToGui::ExternalTempo { bpm: None });
+            }
+
+            // mapped controllers act as if the gui had sent the message
+            while let Some(message) = controlled.pop_front().or_else(|| rx_gui.try_recv().ok()) {
+                match message {
+                    MessageGuiToRho::RowActivations {
+                        row_activations,
+                        row_levels,
+                    } => {
+                        rho.set_row_activations(row_activations);
+                        rho.set_row_levels(row_levels);
+                    }
+                    MessageGuiToRho::HoldNotesEnabled { enabled } => {
+                        hold_enabled = enabled;
+                        rho.set_hold_notes_enabled(enabled);
+                    }
+                    MessageGuiToRho::LearnControl { control } => {
+                        learning = control;
+                    }
+                    MessageGuiToRho::SetMidiMappings {
+                        mappings: new_mappings,
+                    } => {
+                        mappings = new_mappings;
+                    }
+                    MessageGuiToRho::SetRowMuted { row, muted } => {
+                        rho.set_row_muted(row, muted);
+                    }
+                    MessageGuiToRho::SetMidiInPort { port } => {
+                        let old_port = midi_in.port_name().map(str::to_string);
+                        if let Err(e) = midi_in.set_port(port, &ports.inputs) {
+                            let _ = tx.send(MessageToGui::Error {
+                                message: format!("Could not open midi in port: {}", e),
+                            });
+                        }
+                        if midi_in.port_name() != old_port.as_deref() {
+                            release_held_notes(&mut rho, &mut held_notes, |_| false);
+                        }
+                    }
+                    MessageGuiToRho::SetMidiChannelIn { channel } => {
+                        midi_in_channel = channel;
+                        release_held_notes(&mut rho, &mut held_notes, |c| {
+                            channel.map_or(true, |channel| channel == c)
+                        });
+                    }
+                    MessageGuiToRho::SetMidiOutPort { port } => {
+                        stop_all_notes(&mut rho, &mut midi_outs);
+                        routing.main_port = port;
+                        let out_ports = out_ports(&routing, &clock_out_ports);
+                        for message in midi_outs.set_ports(&out_ports, &ports.outputs) {
+                            let _ = tx.send(MessageToGui::Error { message });
+                        }
+                    }
+                    MessageGuiToRho::PortsChanged { ports: new_ports } => {
+                        ports = new_ports;
+                        let old_port = midi_in.port_name().map(str::to_string);
+                        if let Err(e) = midi_in.update_ports(&ports.inputs) {
+                            let _ = tx.send(MessageToGui::Error {
+                                message: format!("Could not reconnect midi in port: {}", e),
+                            });
+                        }
+                        if midi_in.port_name() != old_port.as_deref() {
+                            release_held_notes(&mut rho, &mut held_notes, |_| false);
+                        }
+                        for message in midi_outs.update_ports(&ports.outputs) {
+                            let _ = tx.send(MessageToGui::Error { message });
+                        }
+                    }
+                    MessageGuiToRho::SetMidiChannelOut { channel } => {
+                        // the notes on the old channel would never be stopped
+                        if channel != routing.main_channel {
+                            stop_all_notes(&mut rho, &mut midi_outs);
+                            midi_outs.stop_thru_notes();
+                        }
+                        routing.main_channel = channel;
+                    }
+                    MessageGuiToRho::SetMidiClockOut { port, enabled } => {
+                        if enabled {
+                            if is_playing && !clock_out_ports.contains(&port) {
+                                waiting_clock_ports.insert(port.clone());
+                            }
+                            clock_out_ports.insert(port);
+                        } else {
+                            waiting_clock_ports.remove(&port);
+                            clock_out_ports.remove(&port);
+                        }
+                        let out_ports = out_ports(&routing, &clock_out_ports);
+                        for message in midi_outs.set_ports(&out_ports, &ports.outputs) {
+                            let _ = tx.send(MessageToGui::Error { message });
+                        }
+                    }
+                    MessageGuiToRho::SetMidiThru { thru } => midi_thru = thru,
+                    MessageGuiToRho::SetPlaying { playing } => {
+                        if playing && !is_playing {
+                            if at_start {
+                                scheduler.reset(clock.now());
+                            } else {
+                                scheduler.resume(clock.now());
+                            }
+                        }
+                        if playing != is_playing {
+                            let transport_msg = if !playing {
+                                STOP_MSG
+                            } else if at_start {
+                                START_MSG
+                            } else {
+                                CONTINUE_MSG
+                            };
+                            midi_outs.send_to_ports(&clock_out_ports, &[transport_msg]);
+                            waiting_clock_ports.clear();
+                            at_start = false;
+                        }
+                        if !playing {
+                            stop_all_notes(&mut rho, &mut midi_outs);
+                        }
+                        is_playing = playing;
+                    }
+                    MessageGuiToRho::Panic => {
+                        rho.clear_playing_notes();
+                        midi_outs.panic();
+                    }
+                    MessageGuiToRho::Rewind => {
+                        rho.reset();
+                        scheduler.reset(clock.now());
+                        at_start = true;
+                        // rewinding whilst playing starts again from the top
+                        if is_playing {
+                            midi_outs.send_to_ports(&clock_out_ports, &[START_MSG]);
+                            waiting_clock_ports.clear();
+                            at_start = false;
+                        }
+                    }
+                    MessageGuiToRho::SetTempo { tempo } => {
+                        scheduler.set_tempo(tempo);
+                        sent_tempo = tempo;
+                    }
+                    MessageGuiToRho::RampTempo { tempo, bars } => {
+                        let pulses = bars.max(0.0) * (BEATS_PER_BAR * MIDI_CLOCK_PPQN) as f32;
+                        scheduler.ramp_tempo(tempo, pulses.round() as u64);
+                    }
+                    MessageGuiToRho::SetSwing { swing } => {
+                        scheduler.set_swing(swing);
+                    }
+                    MessageGuiToRho::SetRowRate { row, rate } => {
+                        rho.set_row_rate(row, rate);
+                    }
+                    MessageGuiToRho::SetGate { gate } => {
+                        rho.set_gate(gate);
+                    }
+                    MessageGuiToRho::SetRowGate { row, gate } => {
+                        rho.set_row_gate(row, gate);
+                    }
+                    MessageGuiToRho::SetRowLegato { row, enabled } => {
+                        rho.set_row_legato(row, enabled);
+                    }
+                    MessageGuiToRho::SetCcLane { row, lane } => {
+                        rho.set_cc_lane(row, lane);
+                    }
+                    MessageGuiToRho::SetRowVelocity { row, velocity } => {
+                        rho.set_row_velocity(row, velocity);
+                    }
+                    MessageGuiToRho::SetVelocityCurve { curve } => {
+                        rho.set_velocity_curve(curve);
+                    }
+                    MessageGuiToRho::SetRowDestinations { row, destinations } => {
+                        if row < routing.rows.len() {
+                            let old_targets = routing.targets(row);
+                            routing.rows[row] = destinations;
+                            // the row's notes are stopped where it no longer goes, they end as
+                            // usual everywhere else
+                            let new_targets = routing.targets(row);
+                            let notes = rho.playing_notes_on_row(row);
+                            for (port, channel) in old_targets
+                                .iter()
+                                .filter(|target| !new_targets.contains(target))
+                            {
+                                for note in &notes {
+                                    midi_outs.send(
+                                        port,
+                                        &[
+                                            NOTE_OFF_MSG + channel,
+                                            note.note_number as u8,
+                                            NOTE_OFF_VELOCITY,
+                                        ],
+                                    );
+                                }
+                            }
+                            let out_ports = out_ports(&routing, &clock_out_ports);
+                            for message in midi_outs.set_ports(&out_ports, &ports.outputs) {
+                                let _ = tx.send(MessageToGui::Error { message });
+                            }
+                        }
+                    }
+                    MessageGuiToRho::SetNoteOrdering { ordering } => {
+                        rho.set_note_ordering(ordering);
+                    }
+                    MessageGuiToRho::SetNoteWrapping { wrapping } => {
+                        rho.set_note_wrapping(wrapping);
+                    }
+                    MessageGuiToRho::SetRowAssign { assign } => {
+                        rho.set_row_assign(assign);
+                    }
+                    MessageGuiToRho::SetInvertRows { enabled } => {
+                        rho.set_invert_rows_enabled(enabled);
+                    }
+                    MessageGuiToRho::SetFillOctaves { enabled, fill } => {
+                        rho.set_note_fill(fill);
+                        rho.set_fill_octaves_enabled(enabled);
+                    }
+                    MessageGuiToRho::SetNumRows { num_rows } => {
+                        let num_rows = num_rows.clamp(MIN_NUM_ROWS, MAX_NUM_ROWS);
+                        // stop the removed rows' notes while we still know where they went
+                        let note_offs = rho.set_num_rows(num_rows);
+                        send_note_events(&note_offs, &mut midi_outs, &routing);
+                        routing.set_num_rows(num_rows);
+                        let out_ports = out_ports(&routing, &clock_out_ports);
+                        for message in midi_outs.set_ports(&out_ports, &ports.outputs) {
+                            let _ = tx.send(MessageToGui::Error { message });
+                        }
+                    }
+                    MessageGuiToRho::SetClockSource { source } => {
+                        if source != clock_source {
+                            // the notes were timed by the other clock
+                            stop_all_notes(&mut rho, &mut midi_outs);
+                            // carry on from where the internal clock stopped, rather than
+                            // catching up on the pulses it missed
+                            if source == ClockSource::Internal && is_playing {
+                                scheduler.resume(clock.now());
+                            }
+                        }
+                        clock_source = source;
+                    }
                 }
-                _ => (),
             }
 
-            let mut clock = clock_arc.lock().unwrap();
-
-            if is_playing {
-                let clock_out = clock.tick();
-                if let Some(c) = clock_out {
-                    if c {
-                        // now get the notes to play
-                        let notes_to_play = rho.on_clock_high();
-
-                        for note in notes_to_play {
-                            print!("----------clock------------- OUTPUT note on {}\n", note);
-                            // send midi note on
-                            let midi_out_conn = maybe_midi_out_conn.as_mut().unwrap();
-                            midi_out_conn
-                                .send(&[
-                                    NOTE_ON_MSG + midi_out_channel,
-                                    note.note_number as u8,
-                                    0x64,
-                                ])
-                                .unwrap();
-                        }
-                        tx.send(MessageToGui::Tick {
-                            playing_steps: rho.get_playing_steps(),
-                        })
-                        .unwrap();
-                    } else {
-                        // send midi off for all notes
-                        let notes_to_stop = rho.on_clock_low();
-                        for note in notes_to_stop {
-                            print!("----------clock------------- OUTPUT note off {}\n", note);
-                            // send midi note off
-                            // TODO this can panic!
-                            let midi_out_conn = maybe_midi_out_conn.as_mut().unwrap();
-                            midi_out_conn
-                                .send(&[
-                                    NOTE_OFF_MSG + midi_out_channel,
-                                    note.note_number as u8,
-                                    0x64,
-                                ])
-                                .unwrap();
+            // the internal clock only drives the rows when we aren't following midi clock
+            let internal_clock_running = is_playing && clock_source == ClockSource::Internal;
+            // ramps are measured in pulses, without any to move them along they finish at once
+            if !internal_clock_running {
+                scheduler.finish_ramp();
+            }
+            if internal_clock_running {
+                while let Some(event) = scheduler.poll(clock.now()) {
+                    match event {
+                        ClockEvent::Pulse => {
+                            let pulse = scheduler.pulse_count() - 1;
+                            send_clock_pulse(
+                                pulse,
+                                &mut midi_outs,
+                                &clock_out_ports,
+                                &mut waiting_clock_ports,
+                            );
+                        }
+                        ClockEvent::Tick { index } => {
+                            on_clock_tick(
+                                index,
+                                clock.now(),
+                                &mut rho,
+                                &mut midi_outs,
+                                &routing,
+                                &tx,
+                                &mut sent_playing_steps,
+                            );
                         }
                     }
                 }
+            }
+
+            // keep the tempo slider moving during a ramp, without flooding the gui
+            let tempo = scheduler.tempo();
+            if (tempo - sent_tempo).abs() >= 0.5 || (!scheduler.is_ramping() && tempo != sent_tempo)
+            {
+                sent_tempo = tempo;
+                let _ = tx.send(MessageToGui::Tempo { tempo });
+            }
+
+            // gates in milliseconds don't end on a tick
+            let notes_to_stop = rho.notes_to_stop(clock.now());
+            send_note_events(&notes_to_stop, &mut midi_outs, &routing);
 
+            if is_playing || external_clock.is_playing() {
                 let new_notes_for_rows = rho.get_notes_for_rows();
                 if new_notes_for_rows != sent_notes_for_rows {
                     sent_notes_for_rows = new_notes_for_rows.clone();
@@ -140,12 +471,234 @@ pub fn run_clock(
                 }
             }
 
-            // work out when next clock tick is
-            let accuracy = Duration::from_millis(1);
-            tick_time += Duration::from_millis(period_ms);
-            thread::park_timeout(tick_time - accuracy - Instant::now());
+            // sends are never fatal, but the gui should hear about them
+            for message in midi_outs.take_errors() {
+                let _ = tx.send(MessageToGui::Error { message });
+            }
+
+            let status = EngineStatus {
+                playing: match clock_source {
+                    ClockSource::Internal => is_playing,
+                    ClockSource::ExternalMidi => external_clock.is_playing(),
+                },
+                clock_source,
+                midi_in_port: midi_in.state(),
+                midi_in_channel,
+                midi_out_port: midi_outs.state(routing.main_port.as_deref()),
+                midi_out_channel: routing.main_channel,
+            };
+            if sent_status.as_ref() != Some(&status) {
+                sent_status = Some(status.clone());
+                let _ = tx.send(MessageToGui::Status { status });
+            }
+
+            // wait for the next clock event or note off, or for more messages
+            let next_clock_time = if internal_clock_running {
+                Some(scheduler.next_event_time())
+            } else {
+                None
+            };
+            let next_event_time = next_clock_time
+                .into_iter()
+                .chain(rho.next_note_off_time())
+                .min();
+            clock.wait(next_event_time);
+        }
+
+        // the app is closing
+        stop_all_notes(&mut rho, &mut midi_outs);
+        midi_outs.stop_thru_notes();
+        midi_in.close();
+    })
+}
+
+// stop the notes that have finished, then play the ones triggered by this tick
+fn on_clock_tick<B: MidiBackend>(
+    tick: u64,
+    now: Duration,
+    rho: &mut Rho,
+    midi_outs: &mut MidiOuts<B>,
+    routing: &Routing,
+    tx: &Sender<MessageToGui>,
+    sent_playing_steps: &mut Vec<Option<usize>>,
+) {
+    let note_events = rho.on_tick(tick, now);
+    send_note_events(&note_events, midi_outs, routing);
+    let cc_events = rho.cc_events(tick);
+    send_cc_events(&cc_events, midi_outs, routing);
+
+    // rows step at different rates, so only tell the gui when something moved
+    let playing_steps = rho.get_playing_steps();
+    if playing_steps != *sent_playing_steps {
+        *sent_playing_steps = playing_steps.clone();
+        let _ = tx.send(MessageToGui::Tick { playing_steps });
+    }
+}
+
+// each note goes to every destination of its row
+fn send_note_events<B: MidiBackend>(
+    note_events: &[NoteEvent],
+    midi_outs: &mut MidiOuts<B>,
+    routing: &Routing,
+) {
+    for event in note_events {
+        let (row, status, note, velocity) = match event {
+            NoteEvent::On {
+                row,
+                note,
+                velocity,
+            } => (*row, NOTE_ON_MSG, note, *velocity),
+            NoteEvent::Off { row, note } => (*row, NOTE_OFF_MSG, note, NOTE_OFF_VELOCITY),
+        };
+        for (port, channel) in routing.targets(row) {
+            midi_outs.send(&port, &[status + channel, note.note_number as u8, velocity]);
+        }
+    }
+}
+
+// the ports waiting to start are sent a song position and continue on a sixteenth, so the pulse
+// that follows is where we are
+fn send_clock_pulse<B: MidiBackend>(
+    pulse: u64,
+    midi_outs: &mut MidiOuts<B>,
+    clock_out_ports: &HashSet<String>,
+    waiting_clock_ports: &mut HashSet<String>,
+) {
+    if waiting_clock_ports.is_empty() {
+        midi_outs.send_to_ports(clock_out_ports, &[TIMING_CLOCK_MSG]);
+        return;
+    }
+    if pulse % PULSES_PER_MIDI_BEAT as u64 == 0 {
+        let midi_beats = (pulse / PULSES_PER_MIDI_BEAT as u64).min(0x3FFF) as u16;
+        let position = [
+            SONG_POSITION_MSG,
+            (midi_beats & 0x7F) as u8,
+            (midi_beats >> 7) as u8,
+        ];
+        midi_outs.send_to_ports(waiting_clock_ports, &position);
+        midi_outs.send_to_ports(waiting_clock_ports, &[CONTINUE_MSG]);
+        waiting_clock_ports.clear();
+    }
+    let started: HashSet<String> = clock_out_ports
+        .difference(waiting_clock_ports)
+        .cloned()
+        .collect();
+    midi_outs.send_to_ports(&started, &[TIMING_CLOCK_MSG]);
+}
+
+// the ports that notes or clock are sent to
+fn out_ports(routing: &Routing, clock_out_ports: &HashSet<String>) -> BTreeSet<String> {
+    let mut ports = routing.ports();
+    ports.extend(clock_out_ports.iter().cloned());
+    ports
+}
+
+// what a mapped controller needs to know to toggle things
+struct ControlState<'a> {
+    // the controller has just gone from let go to pressed
+    pressed: bool,
+    is_playing: bool,
+    hold_enabled: bool,
+    rho: &'a Rho,
+}
+
+// a mapped controller was moved. Whatever we own is changed through our own messages and the gui
+// is told, the grid controls are passed on to the gui
+fn on_control(
+    control: Control,
+    value: u8,
+    state: ControlState<'_>,
+    controlled: &mut VecDeque<MessageGuiToRho>,
+    tx: &Sender<MessageToGui>,
+) {
+    let pressed = state.pressed;
+    match control {
+        // the gui follows the tempo as it changes, setting it doesn't tell the gui as that is
+        // usually where it came from
+        Control::Tempo => {
+            let tempo = cc_to_tempo(value);
+            controlled.push_back(MessageGuiToRho::SetTempo { tempo });
+            let _ = tx.send(MessageToGui::Tempo { tempo });
+        }
+        Control::Play if pressed => {
+            let playing = !state.is_playing;
+            controlled.push_back(MessageGuiToRho::SetPlaying { playing });
+            let _ = tx.send(MessageToGui::Playing { playing });
+        }
+        Control::Hold if pressed => {
+            let enabled = !state.hold_enabled;
+            controlled.push_back(MessageGuiToRho::HoldNotesEnabled { enabled });
+            let _ = tx.send(MessageToGui::HoldNotes { enabled });
+        }
+        Control::RowMute(row) if pressed => {
+            let muted = !state.rho.row_muted(row);
+            controlled.push_back(MessageGuiToRho::SetRowMuted { row, muted });
+            let _ = tx.send(MessageToGui::RowMuted { row, muted });
+        }
+        Control::NewDistribution if pressed => {
+            let _ = tx.send(MessageToGui::GridControl { control, value });
+        }
+        Control::Density | Control::RowLength(_) => {
+            let _ = tx.send(MessageToGui::GridControl { control, value });
+        }
+        _ => (),
+    }
+}
+
+// a lane with its own channel sends on it to each of the row's ports
+fn send_cc_events<B: MidiBackend>(
+    cc_events: &[CcEvent],
+    midi_outs: &mut MidiOuts<B>,
+    routing: &Routing,
+) {
+    for event in cc_events {
+        let mut targets = vec![];
+        for (port, channel) in routing.targets(event.row) {
+            let target = (port, event.channel.unwrap_or(channel));
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+        for (port, channel) in targets {
+            midi_outs.send(
+                &port,
+                &[CONTROL_CHANGE_MSG + channel, event.cc, event.value],
+            );
+        }
+    }
+}
+
+// whether a message from the midi in may be passed on, messages on channels we aren't listening to
+// and controllers that drive rho are kept to ourselves
+fn passes_thru(
+    message: &MidiInMessage,
+    in_channel: Option<u8>,
+    mappings: &MidiMappings,
+    learning: Option<Control>,
+) -> bool {
+    let Some(channel) = message.channel() else {
+        return false;
+    };
+    if in_channel.map_or(false, |c| c != channel) {
+        return false;
+    }
+    match *message {
+        MidiInMessage::ControlChange(channel, cc, _) => {
+            learning.is_none() && mappings.control_for(CcSource { channel, cc }).is_none()
         }
-    });
-    // TODO stop playing midi notes!
-    handle
+        _ => true,
+    }
+}
+
+// let go of the keys held on channels we no longer listen to, as we won't hear them let go
+fn release_held_notes(rho: &mut Rho, held_notes: &mut HeldNotes, listening: impl Fn(u8) -> bool) {
+    for note in held_notes.release(listening) {
+        rho.note_off(note.into());
+    }
+}
+
+// nothing should be left sounding when we stop or change where the notes go
+fn stop_all_notes<B: MidiBackend>(rho: &mut Rho, midi_outs: &mut MidiOuts<B>) {
+    rho.clear_playing_notes();
+    midi_outs.stop_all_notes();
 }
@@ -0,0 +1,181 @@
+// where the clock thread gets the time from and how it waits for it. The system clock is the real
+// one, the virtual clock lets tests step the engine from event to event without sleeping
+
+#[cfg(any(test, feature = "test-util"))]
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how often messages are checked for when no clock event is due
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+// how early we wake up before a clock event, the rest of the wait is spent spinning
+const SPIN_TIME: Duration = Duration::from_micros(200);
+
+pub trait EngineClock: Send + 'static {
+    // the time since the clock thread started
+    fn now(&mut self) -> Duration;
+
+    // called at the end of each pass round the loop with the time of the next clock event or note
+    // off, if there is one. returns when it is time for the next pass
+    fn wait(&mut self, next_event_time: Option<Duration>);
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EngineClock for SystemClock {
+    fn now(&mut self) -> Duration {
+        self.start.elapsed()
+    }
+
+    // sleep until just before the next event then spin, sleep alone can oversleep by a lot. Never
+    // sleep so long that messages wait, or past where the spinning should start
+    fn wait(&mut self, next_event_time: Option<Duration>) {
+        let Some(next_event_time) = next_event_time else {
+            thread::sleep(POLL_INTERVAL);
+            return;
+        };
+        let spin_start = next_event_time.saturating_sub(SPIN_TIME);
+        let now = self.start.elapsed();
+        thread::sleep(spin_start.saturating_sub(now).min(POLL_INTERVAL));
+        if self.start.elapsed() >= spin_start {
+            while self.start.elapsed() < next_event_time {
+                thread::yield_now();
+            }
+        }
+    }
+}
+
+// a clock that only moves when the test holding its handle moves it
+#[cfg(any(test, feature = "test-util"))]
+pub struct VirtualClock {
+    now: Duration,
+    tx_waiting: Sender<Option<Duration>>,
+    rx_time: Receiver<Duration>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+pub struct VirtualClockHandle {
+    now: Duration,
+    rx_waiting: Receiver<Option<Duration>>,
+    tx_time: Sender<Duration>,
+    // what the engine is waiting for, None until it has finished its first pass
+    waiting: Option<Option<Duration>>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl VirtualClock {
+    pub fn new() -> (Self, VirtualClockHandle) {
+        let (tx_waiting, rx_waiting) = channel();
+        let (tx_time, rx_time) = channel();
+        let clock = VirtualClock {
+            now: Duration::ZERO,
+            tx_waiting,
+            rx_time,
+        };
+        let handle = VirtualClockHandle {
+            now: Duration::ZERO,
+            rx_waiting,
+            tx_time,
+            waiting: None,
+        };
+        (clock, handle)
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl EngineClock for VirtualClock {
+    fn now(&mut self) -> Duration {
+        self.now
+    }
+
+    // once the handle is dropped the time stops, and the engine can only be stopped
+    fn wait(&mut self, next_event_time: Option<Duration>) {
+        if self.tx_waiting.send(next_event_time).is_ok() {
+            if let Ok(time) = self.rx_time.recv() {
+                self.now = time;
+            }
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl VirtualClockHandle {
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    // move the time on to the given time, letting the engine make a pass now, at every event on
+    // the way and at the end. Anything sent to the engine before this is handled on the first
+    // pass, before the time moves
+    pub fn run_until(&mut self, end: Duration) {
+        if self.waiting.is_none() {
+            self.waiting = Some(self.rx_waiting.recv().unwrap());
+        }
+        let mut time = self.now;
+        loop {
+            self.now = time;
+            self.tx_time.send(time).unwrap();
+            let waiting = self.rx_waiting.recv().unwrap();
+            self.waiting = Some(waiting);
+            if time >= end {
+                return;
+            }
+            time = waiting.filter(|t| *t < end).unwrap_or(end).max(time);
+        }
+    }
+
+    pub fn run_for(&mut self, duration: Duration) {
+        self.run_until(self.now + duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_clock() {
+        let (mut clock, mut handle) = VirtualClock::new();
+        // an engine with events every 10ms
+        let engine = thread::spawn(move || {
+            let mut passes = vec![];
+            for _ in 0..8 {
+                let now = clock.now();
+                passes.push(now);
+                clock.wait(Some(now + Duration::from_millis(10)));
+            }
+            passes
+        });
+
+        // it makes a pass now, then is stepped to each event, then to the end
+        handle.run_until(Duration::from_millis(25));
+        assert_eq!(handle.now(), Duration::from_millis(25));
+        handle.run_for(Duration::from_millis(5));
+        // the time stops once the handle is dropped
+        drop(handle);
+
+        let millis: Vec<u64> = engine
+            .join()
+            .unwrap()
+            .iter()
+            .map(|t| t.as_millis() as u64)
+            .collect();
+        assert_eq!(millis, vec![0, 0, 10, 20, 25, 25, 30, 30]);
+    }
+}
@@ -0,0 +1,169 @@
+// follows an incoming midi clock, turning pulses into clock ticks and measuring the tempo
+
+use crate::rho_config::MIDI_CLOCK_PPQN;
+use std::collections::VecDeque;
+
+// a song position pointer counts midi beats, which are sixteenth notes
+pub const PULSES_PER_MIDI_BEAT: usize = MIDI_CLOCK_PPQN / 4;
+
+// how many pulse intervals are averaged to measure the tempo, one beat's worth
+const NUM_INTERVALS_TO_AVERAGE: usize = MIDI_CLOCK_PPQN;
+
+pub struct ExternalClock {
+    playing: bool,
+    pulse_count: u64,
+    last_stamp: Option<u64>,
+    intervals: VecDeque<u64>,
+}
+
+impl ExternalClock {
+    pub fn new() -> Self {
+        ExternalClock {
+            playing: false,
+            pulse_count: 0,
+            last_stamp: None,
+            intervals: VecDeque::with_capacity(NUM_INTERVALS_TO_AVERAGE),
+        }
+    }
+
+    // midi start, the next pulse is the first step
+    pub fn start(&mut self) {
+        self.pulse_count = 0;
+        self.playing = true;
+    }
+
+    // midi continue, carry on from the current song position
+    pub fn resume(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    // midi song position pointer, in midi beats since the start of the song
+    pub fn set_song_position(&mut self, midi_beats: u16) {
+        self.pulse_count = midi_beats as u64 * PULSES_PER_MIDI_BEAT as u64;
+    }
+
+    // the tick that the next pulse will be
+    pub fn position(&self) -> u64 {
+        self.pulse_count
+    }
+
+    // call for every timing clock message, the stamp is in microseconds.
+    // returns the clock tick for this pulse when we are playing, a pulse is one tick
+    pub fn on_pulse(&mut self, stamp: u64) -> Option<u64> {
+        self.measure_interval(stamp);
+
+        if !self.playing {
+            return None;
+        }
+
+        let tick = self.pulse_count;
+        self.pulse_count += 1;
+        Some(tick)
+    }
+
+    // the tempo of the incoming clock in beats per minute, None until we have enough pulses
+    pub fn bpm(&self) -> Option<f32> {
+        if self.intervals.len() < NUM_INTERVALS_TO_AVERAGE {
+            return None;
+        }
+        let mean_interval_us =
+            self.intervals.iter().sum::<u64>() as f32 / self.intervals.len() as f32;
+        if mean_interval_us <= 0.0 {
+            return None;
+        }
+        Some(60_000_000.0 / (mean_interval_us * MIDI_CLOCK_PPQN as f32))
+    }
+
+    // start measuring again, for when the pulses have stopped coming
+    pub fn forget_tempo(&mut self) {
+        self.last_stamp = None;
+        self.intervals.clear();
+    }
+
+    fn measure_interval(&mut self, stamp: u64) {
+        if let Some(last) = self.last_stamp {
+            if self.intervals.len() == NUM_INTERVALS_TO_AVERAGE {
+                self.intervals.pop_front();
+            }
+            self.intervals.push_back(stamp.saturating_sub(last));
+        }
+        self.last_stamp = Some(stamp);
+    }
+}
+
+impl Default for ExternalClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pulses_to_ticks() {
+        let mut clock = ExternalClock::new();
+
+        // nothing happens until started
+        assert_eq!(clock.on_pulse(0), None);
+
+        clock.start();
+        let ticks: Vec<Option<u64>> = (0..3).map(|i| clock.on_pulse(i)).collect();
+        assert_eq!(ticks, vec![Some(0), Some(1), Some(2)]);
+
+        // stop and continue keeps the position
+        clock.stop();
+        assert_eq!(clock.on_pulse(0), None);
+        clock.resume();
+        assert_eq!(clock.on_pulse(0), Some(3));
+
+        // start goes back to the beginning
+        clock.start();
+        assert_eq!(clock.on_pulse(0), Some(0));
+    }
+
+    #[test]
+    fn test_song_position() {
+        let mut clock = ExternalClock::new();
+
+        // 8 sixteenths is two beats
+        clock.set_song_position(8);
+        assert_eq!(clock.position(), 2 * MIDI_CLOCK_PPQN as u64);
+
+        clock.resume();
+        assert_eq!(clock.on_pulse(0), Some(2 * MIDI_CLOCK_PPQN as u64));
+    }
+
+    #[test]
+    fn test_bpm() {
+        let mut clock = ExternalClock::new();
+        // 120 bpm is 2 beats per second, 48 pulses per second
+        let interval_us = 1_000_000 / 48;
+        for i in 0..NUM_INTERVALS_TO_AVERAGE {
+            clock.on_pulse(i as u64 * interval_us);
+        }
+        assert_eq!(clock.bpm(), None);
+
+        clock.on_pulse(NUM_INTERVALS_TO_AVERAGE as u64 * interval_us);
+        let bpm = clock.bpm().unwrap();
+        assert!((bpm - 120.0).abs() < 0.1);
+
+        // the gap before the pulses come back isn't measured
+        clock.forget_tempo();
+        assert_eq!(clock.bpm(), None);
+        let restart = 60 * 1_000_000;
+        for i in 0..=NUM_INTERVALS_TO_AVERAGE {
+            clock.on_pulse(restart + i as u64 * interval_us);
+        }
+        assert!((clock.bpm().unwrap() - 120.0).abs() < 0.1);
+    }
+}
@@ -0,0 +1,79 @@
+// how long notes play for
+
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GateLength {
+    // percentage of the row's step
+    Percent(f32),
+    Millis(f32),
+}
+
+// when a playing note should stop
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoteEnd {
+    Tick(u64),
+    Time(Duration),
+    // held until the row's next step, for legato
+    NextStep,
+}
+
+impl GateLength {
+    // the end of a note started on this tick at this time, on a row whose steps are this long
+    pub fn note_end(&self, tick: u64, now: Duration, ticks_per_step: usize) -> NoteEnd {
+        match *self {
+            GateLength::Percent(percent) => {
+                let gate_ticks = (ticks_per_step as f32 * percent / 100.0).round() as u64;
+                NoteEnd::Tick(tick + gate_ticks.max(1))
+            }
+            GateLength::Millis(ms) => {
+                let gate_us = (ms.max(1.0) * 1000.0).round() as u64;
+                NoteEnd::Time(now + Duration::from_micros(gate_us))
+            }
+        }
+    }
+}
+
+impl Default for GateLength {
+    fn default() -> Self {
+        GateLength::Percent(50.0)
+    }
+}
+
+impl fmt::Display for GateLength {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GateLength::Percent(percent) => write!(f, "{:.0}%", percent),
+            GateLength::Millis(ms) => write!(f, "{:.0}ms", ms),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_note_end() {
+        let now = Duration::from_secs(1);
+
+        assert_eq!(
+            GateLength::Percent(50.0).note_end(100, now, 24),
+            NoteEnd::Tick(112)
+        );
+        assert_eq!(
+            GateLength::Percent(100.0).note_end(100, now, 24),
+            NoteEnd::Tick(124)
+        );
+        // always at least one tick long
+        assert_eq!(
+            GateLength::Percent(0.0).note_end(100, now, 24),
+            NoteEnd::Tick(101)
+        );
+        assert_eq!(
+            GateLength::Millis(250.0).note_end(100, now, 24),
+            NoteEnd::Time(Duration::from_millis(1250))
+        );
+    }
+}
@@ -1,10 +1,8 @@
 #![allow(dead_code)]
 
-use crate::rand::prelude::SliceRandom;
+use rand::prelude::SliceRandom;
 use rand::thread_rng;
 
-use crate::rho_config::NUM_ROWS;
-
 //--------------------------------------------------------------------------------
 // TODOs
 //
@@ -77,11 +75,16 @@ pub fn grid_index_to_flat_index(grid_index: (usize, usize), row_lengths: &Vec<us
 
     // allow an index just off end
     debug_assert!(flat <= row_lengths.iter().sum());
-    return flat;
+    flat
 }
 
+// how hard a step plays, as a share of the row's velocity. New steps play at full level
+pub const DEFAULT_STEP_LEVEL: f32 = 1.0;
+
 pub struct GridActivations {
     active: Vec<bool>,
+    // one for each step, kept when the step is switched off
+    levels: Vec<f32>,
     thresh: Vec<usize>,
     row_lengths: Vec<usize>,
     // these suck because they both interdepend on the steps
@@ -93,6 +96,7 @@ impl GridActivations {
         let total_steps = steps * rows;
         GridActivations {
             active: vec![false; total_steps],
+            levels: vec![DEFAULT_STEP_LEVEL; total_steps],
             thresh: create_new_distribution(total_steps),
             row_lengths: vec![steps; rows],
             normalized_density: 0.0,
@@ -104,7 +108,6 @@ impl GridActivations {
     }
 
     pub fn set_normalized_density(&mut self, density: f32) {
-        print!("set_normalized_density {}\n", density);
         self.normalized_density = density;
         let wanted_num_active_steps = (density * self.get_total_num_steps() as f32) as usize;
 
@@ -118,10 +121,10 @@ impl GridActivations {
     }
 
     pub fn set_row_length(&mut self, row_index: usize, new_length: usize) {
-        if new_length > self.row_lengths[row_index] {
-            self.append_steps(row_index, new_length);
-        } else if new_length < self.row_lengths[row_index] {
-            self.remove_steps(row_index, new_length);
+        match new_length.cmp(&self.row_lengths[row_index]) {
+            std::cmp::Ordering::Greater => self.append_steps(row_index, new_length),
+            std::cmp::Ordering::Less => self.remove_steps(row_index, new_length),
+            std::cmp::Ordering::Equal => (),
         }
     }
 
@@ -138,14 +141,45 @@ impl GridActivations {
         self.active[start..end].to_vec()
     }
 
-    pub fn get_row_activations(&self) -> [Vec<bool>; NUM_ROWS] {
-        let mut result: [Vec<bool>; NUM_ROWS] = Default::default();
-        for i in 0..self.row_lengths.len() {
-            result[i] = self.get_row(i);
+    pub fn get_row_activations(&self) -> Vec<Vec<bool>> {
+        (0..self.row_lengths.len())
+            .map(|row| self.get_row(row))
+            .collect()
+    }
+
+    pub fn get_row_levels(&self) -> Vec<Vec<f32>> {
+        let mut result = vec![];
+        let mut start = 0;
+        for length in self.row_lengths.iter() {
+            result.push(self.levels[start..start + length].to_vec());
+            start += length;
         }
         result
     }
 
+    pub fn num_rows(&self) -> usize {
+        self.row_lengths.len()
+    }
+
+    // rows are added and removed at the top, new rows have the given length and are inactive.
+    // The other rows keep their steps and thresholds
+    pub fn set_num_rows(&mut self, num_rows: usize, new_row_length: usize) {
+        while self.row_lengths.len() > num_rows {
+            let last = self.row_lengths.len() - 1;
+            if self.row_lengths[last] > 0 {
+                self.remove_steps(last, 0);
+            }
+            self.row_lengths.pop();
+        }
+        while self.row_lengths.len() < num_rows {
+            self.row_lengths.push(0);
+            if new_row_length > 0 {
+                self.append_steps(self.row_lengths.len() - 1, new_row_length);
+            }
+        }
+        self.update_density();
+    }
+
     fn num_active_steps(&self) -> usize {
         self.active
             .iter()
@@ -157,6 +191,15 @@ impl GridActivations {
         self.change_step_update_thresholds(flat_index, on);
     }
 
+    pub fn get_level(&self, row: usize, step: usize) -> f32 {
+        self.levels[grid_index_to_flat_index((row, step), &self.row_lengths)]
+    }
+
+    pub fn set_level(&mut self, row: usize, step: usize, level: f32) {
+        let flat_index = grid_index_to_flat_index((row, step), &self.row_lengths);
+        self.levels[flat_index] = level.clamp(0.0, 1.0);
+    }
+
     // switch a step on or off
     //  adjust distribution  whilst respecting the changed step (step at index)
     // if something changed, returns true
@@ -224,16 +267,20 @@ impl GridActivations {
         thresh_to_insert.shuffle(&mut rng);
 
         let active_to_insert = vec![false; num_to_insert];
+        let levels_to_insert = vec![DEFAULT_STEP_LEVEL; num_to_insert];
 
         let insert_position = grid_index_to_flat_index((row_to_append + 1, 0), &self.row_lengths);
 
         self.active
             .splice(insert_position..insert_position, active_to_insert);
+        self.levels
+            .splice(insert_position..insert_position, levels_to_insert);
         self.thresh
             .splice(insert_position..insert_position, thresh_to_insert);
 
         // @todo is there some nice way to assert this always happens for any mutation
         debug_assert!(self.active.len() == self.thresh.len());
+        debug_assert!(self.active.len() == self.levels.len());
 
         self.row_lengths[row_to_append] = new_length;
         self.update_density();
@@ -253,6 +300,7 @@ impl GridActivations {
             // erase the active step and the thresh at that point
             self.thresh.remove(remove_position);
             self.active.remove(remove_position);
+            self.levels.remove(remove_position);
 
             // all the thresholds higher than the removed one need to be reduced by one
             self.thresh.iter_mut().for_each(|x| {
@@ -303,6 +351,7 @@ mod tests {
     fn test_set_activations_for_new_density() {
         let mut seq = GridActivations {
             active: vec![false, false, false, false, false],
+            levels: vec![DEFAULT_STEP_LEVEL; 5],
             thresh: vec![0, 1, 2, 4, 3],
             row_lengths: vec![1, 2, 3],
             normalized_density: 0.0,
@@ -321,6 +370,7 @@ mod tests {
     fn test_num_active_steps() {
         let mut seq = GridActivations {
             active: vec![false, true, false, false, true],
+            levels: vec![DEFAULT_STEP_LEVEL; 5],
             thresh: vec![0, 1, 2, 4, 3],
             row_lengths: vec![1, 2, 3],
             normalized_density: 0.0,
@@ -335,6 +385,7 @@ mod tests {
     fn test_change_step() {
         let mut seq = GridActivations {
             active: vec![false, false, false, false, false],
+            levels: vec![DEFAULT_STEP_LEVEL; 5],
             thresh: vec![0, 1, 2, 3, 4],
             row_lengths: vec![1, 2, 3],
             normalized_density: 0.0,
@@ -365,6 +416,7 @@ mod tests {
     fn test_create_new_distribution_given_active_steps() {
         let mut seq = GridActivations {
             active: vec![false, true, false, false, true],
+            levels: vec![DEFAULT_STEP_LEVEL; 5],
             thresh: vec![0, 1, 2, 3, 4],
             row_lengths: vec![1, 2, 3],
             normalized_density: 0.0,
@@ -432,6 +484,7 @@ mod tests {
     fn test_append_steps() {
         let mut seq = GridActivations {
             active: vec![true, true, true, true, true, true],
+            levels: vec![DEFAULT_STEP_LEVEL; 6],
             thresh: vec![0, 1, 2, 3, 4, 5],
             row_lengths: vec![1, 2, 3],
             normalized_density: 0.0,
@@ -459,6 +512,7 @@ mod tests {
     fn test_append_steps_edge_cases() {
         let mut seq = GridActivations {
             active: vec![],
+            levels: vec![],
             thresh: vec![],
             row_lengths: vec![0, 0, 0],
             normalized_density: 0.0,
@@ -475,6 +529,7 @@ mod tests {
     fn test_remove_steps() {
         let mut seq = GridActivations {
             active: vec![true, true, true, false, false, false],
+            levels: vec![DEFAULT_STEP_LEVEL; 6],
             thresh: vec![0, 1, 2, 3, 4, 5],
             row_lengths: vec![1, 2, 3],
             normalized_density: 0.0,
@@ -494,4 +549,44 @@ mod tests {
 
         assert_eq!(seq.normalized_density, 2.0 / 5.0);
     }
+
+    #[test]
+    fn test_set_num_rows() {
+        let mut seq = GridActivations::new(2, 2);
+        seq.set(0, 1, true);
+        seq.set(1, 0, true);
+
+        seq.set_num_rows(3, 4);
+        assert_eq!(seq.num_rows(), 3);
+        assert_eq!(
+            seq.get_row_activations(),
+            vec![vec![false, true], vec![true, false], vec![false; 4]]
+        );
+
+        // the rows that are left keep their steps, and the thresholds still cover every step
+        seq.set_num_rows(1, 4);
+        assert_eq!(seq.get_row_activations(), vec![vec![false, true]]);
+        let mut thresh = seq.thresh.clone();
+        thresh.sort();
+        assert_eq!(thresh, vec![0, 1]);
+        assert_eq!(seq.get_normalized_density(), 0.5);
+    }
+
+    #[test]
+    fn test_step_levels() {
+        let mut seq = GridActivations::new(2, 2);
+        seq.set_level(0, 1, 0.5);
+        seq.set_level(1, 0, 2.0);
+        assert_eq!(seq.get_level(0, 1), 0.5);
+        // kept in range
+        assert_eq!(seq.get_level(1, 0), 1.0);
+
+        // levels move with their steps when rows change length
+        seq.append_steps(0, 3);
+        seq.set_level(1, 1, 0.25);
+        seq.remove_steps(0, 2);
+        let levels = seq.get_row_levels();
+        assert_eq!(levels[0], vec![1.0, 0.5]);
+        assert_eq!(levels[1], vec![1.0, 0.25]);
+    }
 }
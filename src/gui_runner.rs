@@ -1,137 +1,474 @@
 // run the egui update function
 
+use crate::cc_lane::{CcLane, MAX_CC_VALUE};
+use crate::gate::GateLength;
 use crate::grid_activations::GridActivations;
 use crate::messages::*;
-use crate::rho_config::NUM_ROWS;
+use crate::midi_learn::{cc_to_density, cc_to_row_length, Control, MidiMappings};
+use crate::midi_thru::MidiThru;
+use crate::note_assigner::{NoteOrdering, NoteWrapping, RowAssign, NOTE_ORDERINGS, NOTE_WRAPPINGS};
+use crate::note_fill::{parse_intervals, FillDirection, FillInterval, NoteFill};
+use crate::port_watcher::MidiPorts;
+use crate::rho_config::{
+    DEFAULT_NUM_ROWS, MAX_NUM_ROWS, MAX_ROW_LENGTH, MIN_NUM_ROWS, MIN_ROW_LENGTH,
+};
+use crate::routing::{Routing, RowDestination};
+use crate::row_rate::{RowRate, ROW_RATES};
+use crate::scheduler::{MAX_SWING, MIN_SWING};
 use crate::step_switch::*;
+use crate::tap_tempo::TapTempo;
+use crate::velocity::{VelocityCurve, VelocityMode, MAX_VELOCITY, VELOCITY_CURVES};
 use eframe::egui;
-use midir::{MidiInput, MidiOutput};
+use std::collections::HashSet;
+use std::sync::mpsc::{Receiver, Sender};
 use std::time::Duration;
 
+const MIN_TEMPO: f32 = 40.0;
+const MAX_TEMPO: f32 = 1000.0;
+// the length of the first rows and of rows that are added
+const NEW_ROW_LENGTH: usize = 4;
+
+// the key the midi settings are saved under
+const MIDI_SETTINGS_KEY: &str = "midi_settings";
+
+// the midi setup, remembered between sessions. Ports are remembered by name so we find the
+// same device whichever order things are plugged in
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct MidiSettings {
+    in_port: Option<String>,
+    out_port: Option<String>,
+    in_channel: u8,
+    // notes on every channel are played, the channel is ignored
+    in_omni: bool,
+    out_channel: u8,
+    // output ports that midi clock is sent to
+    clock_out_ports: HashSet<String>,
+    // where each row's notes are sent, as well as or instead of the main output. Kept for rows
+    // that have been removed, so they come back the same
+    row_destinations: Vec<Vec<RowDestination>>,
+    // the controllers that drive the sequencer's controls
+    mappings: MidiMappings,
+    // what is passed from the input to the output
+    thru: MidiThru,
+}
+
+impl Default for MidiSettings {
+    fn default() -> Self {
+        Self {
+            in_port: None,
+            out_port: None,
+            in_channel: 0,
+            in_omni: true,
+            out_channel: 0,
+            clock_out_ports: HashSet::new(),
+            row_destinations: Routing::new().rows,
+            mappings: MidiMappings::new(),
+            thru: MidiThru::new(),
+        }
+    }
+}
+
 struct UiState {
     // these vars are persistent across frames
-    selected_in_port: usize,
-    selected_out_port: usize,
-    midi_in_channel: u8,
-    midi_out_channel: u8,
+    midi: MidiSettings,
+    // the ports the port watcher last found
+    ports: MidiPorts,
     note_strings_for_rows: Vec<String>,
     hold_checkbox_enabled: bool,
-    playing_steps_for_rows: [Option<usize>; NUM_ROWS],
+    playing_steps_for_rows: Vec<Option<usize>>,
+    row_rates: Vec<RowRate>,
+    gate: GateLength,
+    // None when the row uses the global gate
+    row_gates: Vec<Option<GateLength>>,
+    row_legato: Vec<bool>,
+    row_velocities: Vec<VelocityMode>,
+    row_muted: Vec<bool>,
+    cc_lanes: Vec<CcLane>,
+    velocity_curve: VelocityCurve,
+    note_ordering: NoteOrdering,
+    note_wrapping: NoteWrapping,
+    // held notes keep their rows
+    pin_rows: bool,
+    invert_rows: bool,
+    fill_octaves: bool,
+    note_fill: NoteFill,
+    // the custom fill intervals as they are typed
+    fill_intervals_text: String,
     playing: bool,
     tempo: f32,
+    tap_tempo: TapTempo,
+    ramp_tempo: f32,
+    ramp_bars: f32,
+    // swing as a percentage, 50 is straight
+    swing_percent: f32,
+    clock_source: ClockSource,
+    // tempo of the incoming midi clock, if there is one
+    external_bpm: Option<f32>,
+    // the last status from the engine, None until it has started
+    status: Option<EngineStatus>,
+    // the last error, until it is dismissed
+    error: Option<String>,
+    // clicking a control picks it to be mapped to the next controller that is moved
+    learn_mode: bool,
+    learn_target: Option<Control>,
 }
 
 impl UiState {
     fn new() -> Self {
         Self {
-            selected_in_port: 0,
-            selected_out_port: 0,
-            midi_in_channel: 0,
-            midi_out_channel: 0,
-            note_strings_for_rows: vec!["".to_string(); NUM_ROWS],
+            midi: MidiSettings::default(),
+            ports: MidiPorts::default(),
+            note_strings_for_rows: vec!["".to_string(); DEFAULT_NUM_ROWS],
             hold_checkbox_enabled: false,
-            playing_steps_for_rows: [None; NUM_ROWS],
+            playing_steps_for_rows: vec![None; DEFAULT_NUM_ROWS],
+            row_rates: vec![RowRate::default(); DEFAULT_NUM_ROWS],
+            gate: GateLength::default(),
+            row_gates: vec![None; DEFAULT_NUM_ROWS],
+            row_legato: vec![false; DEFAULT_NUM_ROWS],
+            row_velocities: vec![VelocityMode::default(); DEFAULT_NUM_ROWS],
+            row_muted: vec![false; DEFAULT_NUM_ROWS],
+            cc_lanes: vec![CcLane::default(); DEFAULT_NUM_ROWS],
+            velocity_curve: VelocityCurve::default(),
+            note_ordering: NoteOrdering::LowestFirst,
+            note_wrapping: NoteWrapping::Fold,
+            pin_rows: false,
+            invert_rows: false,
+            fill_octaves: false,
+            note_fill: NoteFill::new(),
+            fill_intervals_text: "7, 5".to_string(),
             playing: false,
             tempo: 120.0,
+            tap_tempo: TapTempo::new(),
+            ramp_tempo: 120.0,
+            ramp_bars: 4.0,
+            swing_percent: 50.0,
+            clock_source: ClockSource::Internal,
+            external_bpm: None,
+            status: None,
+            error: None,
+            learn_mode: false,
+            learn_target: None,
         }
     }
 }
 
-// gui takes ownership of the grid
-pub fn run_gui(
-    rx: std::sync::mpsc::Receiver<MessageToGui>,
-    tx: std::sync::mpsc::Sender<MessageGuiToRho>,
-) {
-    let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default().with_inner_size([600.0, 600.0]),
-        default_theme: eframe::Theme::Dark,
-        follow_system_theme: false,
-        ..Default::default()
-    };
+// the gui state and the channels to and from the clock thread, updated once per frame by the app
+pub struct Gui {
+    ui_state: UiState,
+    grid: GridActivations,
+    rx: Receiver<MessageToGui>,
+    tx: Sender<MessageGuiToRho>,
+}
 
-    let mut ui_state = UiState::new();
+impl Gui {
+    pub fn new(
+        rx: Receiver<MessageToGui>,
+        tx: Sender<MessageGuiToRho>,
+        storage: Option<&dyn eframe::Storage>,
+    ) -> Self {
+        let mut ui_state = UiState::new();
+        if let Some(storage) = storage {
+            ui_state.midi = eframe::get_value(storage, MIDI_SETTINGS_KEY).unwrap_or_default();
+        }
+        let destinations = &mut ui_state.midi.row_destinations;
+        if destinations.len() < DEFAULT_NUM_ROWS {
+            destinations.resize(DEFAULT_NUM_ROWS, vec![RowDestination::default()]);
+        }
 
-    // grid could go in UiState too
-    let mut grid = GridActivations::new(4, 4);
+        let gui = Self {
+            ui_state,
+            grid: GridActivations::new(DEFAULT_NUM_ROWS, NEW_ROW_LENGTH),
+            rx,
+            tx,
+        };
+        gui.send_initial_state();
+        gui
+    }
 
-    // TODO send all the intial gui state to Rho
-    let _ = tx.send(MessageGuiToRho::SetTempo {
-        tempo: ui_state.tempo,
-    });
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, MIDI_SETTINGS_KEY, &self.ui_state.midi);
+    }
 
-    let _ = tx.send(MessageGuiToRho::RowActivations {
-        row_activations: grid.get_row_activations(),
-    });
+    // send all the intial gui state to Rho
+    fn send_initial_state(&self) {
+        let midi = &self.ui_state.midi;
+        let _ = self.tx.send(MessageGuiToRho::SetMidiInPort {
+            port: midi.in_port.clone(),
+        });
+        let _ = self.tx.send(MessageGuiToRho::SetMidiChannelIn {
+            channel: in_channel(midi),
+        });
+        let _ = self.tx.send(MessageGuiToRho::SetMidiOutPort {
+            port: midi.out_port.clone(),
+        });
+        let _ = self.tx.send(MessageGuiToRho::SetMidiChannelOut {
+            channel: midi.out_channel,
+        });
+        for port in &midi.clock_out_ports {
+            let _ = self.tx.send(MessageGuiToRho::SetMidiClockOut {
+                port: port.clone(),
+                enabled: true,
+            });
+        }
+        let _ = self.tx.send(MessageGuiToRho::SetMidiMappings {
+            mappings: midi.mappings.clone(),
+        });
+        let _ = self.tx.send(MessageGuiToRho::SetMidiThru {
+            thru: midi.thru.clone(),
+        });
+        let num_rows = self.grid.num_rows();
+        for (row, destinations) in midi.row_destinations.iter().enumerate().take(num_rows) {
+            let _ = self.tx.send(MessageGuiToRho::SetRowDestinations {
+                row,
+                destinations: destinations.clone(),
+            });
+        }
+
+        let _ = self.tx.send(MessageGuiToRho::SetTempo {
+            tempo: self.ui_state.tempo,
+        });
+
+        let _ = self.tx.send(MessageGuiToRho::RowActivations {
+            row_activations: self.grid.get_row_activations(),
+            row_levels: self.grid.get_row_levels(),
+        });
+    }
+
+    pub fn update(&mut self, ctx: &egui::Context) {
+        let ui_state = &mut self.ui_state;
+        let grid = &mut self.grid;
+        let rx = &self.rx;
+        let tx = &self.tx;
 
-    let _ = eframe::run_simple_native("My egui App", options, move |ctx, _frame| {
         // these vars are reset each frame
         let mut do_send_row_activations = false;
 
-        top_panel(ctx, &mut ui_state, &tx);
+        top_panel(ctx, ui_state, tx);
+        status_bar(ctx, ui_state);
 
         egui::CentralPanel::default().show(ctx, |ui| {
             // first recieve messages from the clock thread
-            match rx.try_recv() {
-                Ok(MessageToGui::Tick { playing_steps }) => {
-                    ui_state.playing_steps_for_rows = playing_steps;
-                    ctx.request_repaint();
-                }
-                Ok(MessageToGui::NotesForRows { notes }) => {
-                    // assign notes to the note_strings_for_rows
-                    for i in 0..NUM_ROWS {
-                        let mut note_str = String::new();
-                        for note in notes[i].iter() {
-                            note_str.push_str(&format!("{} ", note));
-                        }
-                        ui_state.note_strings_for_rows[i] = note_str.clone();
+            while let Ok(message) = rx.try_recv() {
+                match message {
+                    MessageToGui::Tick { playing_steps } => {
+                        ui_state.playing_steps_for_rows = playing_steps;
                         ctx.request_repaint();
                     }
+                    MessageToGui::NotesForRows { notes } => {
+                        // assign notes to the note_strings_for_rows
+                        ui_state
+                            .note_strings_for_rows
+                            .resize(notes.len(), String::new());
+                        for (i, row_notes) in notes.iter().enumerate() {
+                            let mut note_str = String::new();
+                            for note in row_notes.iter() {
+                                note_str.push_str(&format!("{} ", note));
+                            }
+                            ui_state.note_strings_for_rows[i] = note_str.clone();
+                            ctx.request_repaint();
+                        }
+                    }
+                    MessageToGui::ExternalTempo { bpm } => {
+                        ui_state.external_bpm = bpm;
+                    }
+                    MessageToGui::Tempo { tempo } => {
+                        ui_state.tempo = tempo;
+                    }
+                    MessageToGui::Error { message } => {
+                        log::warn!("{}", message);
+                        ui_state.error = Some(message);
+                    }
+                    MessageToGui::Status { status } => {
+                        ui_state.status = Some(status);
+                    }
+                    MessageToGui::Ports { ports } => {
+                        ui_state.ports = ports;
+                    }
+                    MessageToGui::Learned { source, control } => {
+                        ui_state.midi.mappings.learn(source, control);
+                        ui_state.learn_target = None;
+                    }
+                    MessageToGui::Playing { playing } => {
+                        ui_state.playing = playing;
+                    }
+                    MessageToGui::HoldNotes { enabled } => {
+                        ui_state.hold_checkbox_enabled = enabled;
+                    }
+                    MessageToGui::RowMuted { row, muted } => {
+                        if let Some(row_muted) = ui_state.row_muted.get_mut(row) {
+                            *row_muted = muted;
+                        }
+                    }
+                    MessageToGui::GridControl { control, value } => {
+                        match control {
+                            Control::Density => {
+                                grid.set_normalized_density(cc_to_density(value));
+                            }
+                            // only sent when the button is pressed
+                            Control::NewDistribution => {
+                                grid.create_new_distribution_given_active_steps();
+                            }
+                            Control::RowLength(row) if row < grid.num_rows() => {
+                                set_row_length(grid, ui_state, tx, row, cc_to_row_length(value));
+                            }
+                            _ => (),
+                        }
+                        do_send_row_activations = true;
+                    }
                 }
-                _ => (),
             }
 
             let mut density: usize = (grid.get_normalized_density() * 127.0) as usize;
 
-            for row in (0..NUM_ROWS).rev() {
-                let playing_step = ui_state.playing_steps_for_rows[row];
-                do_send_row_activations = do_send_row_activations
-                    || draw_row(ui, &mut grid, &mut ui_state, row, playing_step);
-            }
+            // lots of rows scroll, leaving room for the controls underneath
+            let rows_height = ui.available_height() - 70.0;
+            egui::ScrollArea::vertical()
+                .max_height(rows_height)
+                .show(ui, |ui| {
+                    for row in (0..grid.num_rows()).rev() {
+                        let playing_step =
+                            ui_state.playing_steps_for_rows.get(row).copied().flatten();
+                        do_send_row_activations = do_send_row_activations
+                            || draw_row(ui, grid, ui_state, tx, row, playing_step);
+                    }
+                });
 
-            ui.horizontal(|ui| {
-                if ui
-                    .add(egui::Slider::new(&mut density, 0..=127).text("density"))
-                    .changed()
-                {
+            ui.horizontal_wrapped(|ui| {
+                let response = ui.add(egui::Slider::new(&mut density, 0..=127).text("density"));
+                if response.changed() {
                     let norm_density = density as f32 / 127.0;
                     grid.set_normalized_density(norm_density);
                     do_send_row_activations = true;
                 }
+                learnable(ui, ui_state, tx, &response, Control::Density);
 
-                if ui.button("New Dist").clicked() {
+                let response = ui.button("New Dist");
+                if response.clicked() {
                     grid.create_new_distribution_given_active_steps();
                     do_send_row_activations = true;
                 }
+                learnable(ui, ui_state, tx, &response, Control::NewDistribution);
+
+                let response = ui.checkbox(&mut ui_state.hold_checkbox_enabled, "Hold");
+                if response.changed() {
+                    let _ = tx.send(MessageGuiToRho::HoldNotesEnabled {
+                        enabled: ui_state.hold_checkbox_enabled,
+                    });
+                }
+                learnable(ui, ui_state, tx, &response, Control::Hold);
+
+                // how the held notes are spread over the rows
+                let mut changed = false;
+                egui::ComboBox::from_id_source("note_ordering")
+                    .selected_text(ui_state.note_ordering.to_string())
+                    .show_ui(ui, |ui| {
+                        for ordering in NOTE_ORDERINGS {
+                            changed |= ui
+                                .selectable_value(
+                                    &mut ui_state.note_ordering,
+                                    ordering,
+                                    ordering.to_string(),
+                                )
+                                .changed();
+                        }
+                    });
+                if changed {
+                    let _ = tx.send(MessageGuiToRho::SetNoteOrdering {
+                        ordering: ui_state.note_ordering,
+                    });
+                }
+
+                let mut changed = false;
+                egui::ComboBox::from_id_source("note_wrapping")
+                    .selected_text(format!("Wrap: {}", ui_state.note_wrapping))
+                    .show_ui(ui, |ui| {
+                        for wrapping in NOTE_WRAPPINGS {
+                            changed |= ui
+                                .selectable_value(
+                                    &mut ui_state.note_wrapping,
+                                    wrapping,
+                                    wrapping.to_string(),
+                                )
+                                .changed();
+                        }
+                    });
+                if changed {
+                    let _ = tx.send(MessageGuiToRho::SetNoteWrapping {
+                        wrapping: ui_state.note_wrapping,
+                    });
+                }
 
                 if ui
-                    .checkbox(&mut ui_state.hold_checkbox_enabled, "Hold")
+                    .checkbox(&mut ui_state.pin_rows, "Pin")
+                    .on_hover_text("Held notes keep their rows, new notes take the free ones")
                     .changed()
                 {
-                    let _ = tx.send(MessageGuiToRho::HoldNotesEnabled {
-                        enabled: ui_state.hold_checkbox_enabled,
+                    let assign = if ui_state.pin_rows {
+                        RowAssign::Hold
+                    } else {
+                        RowAssign::Dynamic
+                    };
+                    let _ = tx.send(MessageGuiToRho::SetRowAssign { assign });
+                }
+
+                if ui
+                    .checkbox(&mut ui_state.invert_rows, "Invert")
+                    .on_hover_text("The first note goes to the top row")
+                    .changed()
+                {
+                    let _ = tx.send(MessageGuiToRho::SetInvertRows {
+                        enabled: ui_state.invert_rows,
+                    });
+                }
+
+                let mut fill_changed = ui
+                    .checkbox(&mut ui_state.fill_octaves, "Fill")
+                    .on_hover_text("Fill the empty rows with copies of the held notes")
+                    .changed();
+                let fill_text = format!(
+                    "{} {}",
+                    ui_state.note_fill.interval, ui_state.note_fill.direction
+                );
+                ui.menu_button(fill_text, |ui| {
+                    fill_changed |= note_fill_editor(
+                        ui,
+                        &mut ui_state.note_fill,
+                        &mut ui_state.fill_intervals_text,
+                    );
+                });
+                if fill_changed {
+                    let _ = tx.send(MessageGuiToRho::SetFillOctaves {
+                        enabled: ui_state.fill_octaves,
+                        fill: ui_state.note_fill.clone(),
                     });
                 }
+
+                let mut num_rows = grid.num_rows();
+                if ui
+                    .add(
+                        egui::DragValue::new(&mut num_rows)
+                            .clamp_range(MIN_NUM_ROWS..=MAX_NUM_ROWS),
+                    )
+                    .on_hover_text("The number of rows")
+                    .changed()
+                {
+                    set_num_rows(grid, ui_state, tx, num_rows);
+                    do_send_row_activations = true;
+                }
+                ui.label("Rows");
             });
 
             if do_send_row_activations {
                 let _ = tx.send(MessageGuiToRho::RowActivations {
                     row_activations: grid.get_row_activations(),
+                    row_levels: grid.get_row_levels(),
                 });
             }
 
             ctx.request_repaint_after(Duration::from_millis(100));
         });
-    });
+    }
 }
 
 // draw a single row: todo make it stretchy
@@ -139,6 +476,7 @@ fn draw_row(
     ui: &mut egui::Ui,
     grid: &mut GridActivations,
     ui_state: &mut UiState,
+    tx: &Sender<MessageGuiToRho>,
     row: usize,
     playing_step: Option<usize>,
 ) -> bool {
@@ -150,12 +488,17 @@ fn draw_row(
         let spacing = ui.spacing().item_spacing;
 
         let fixed_left_width = 100.0;
-        let fixed_right_width = 200.0;
+        let fixed_right_width = 510.0;
 
         // a text display of the note for this row
         ui.add_sized(
             [fixed_left_width, 50.0],
-            egui::Label::new(&ui_state.note_strings_for_rows[row]),
+            egui::Label::new(
+                ui_state
+                    .note_strings_for_rows
+                    .get(row)
+                    .map_or("", String::as_str),
+            ),
         );
 
         // draw the row of steps
@@ -169,135 +512,926 @@ fn draw_row(
         let step_width = steps_width / row_length as f32;
         for step in 0..row_length {
             let mut active = grid.get(row, step);
+            let mut level = grid.get_level(row, step);
             let is_playing = playing_step == Some(step);
 
-            // set the size on this step switch
+            // set the size on this step switch. click to switch it, drag up or down for its level
             if ui
-                .add_sized([step_width, 50.0], step_switch(&mut active, is_playing))
+                .add_sized(
+                    [step_width, 50.0],
+                    step_switch(&mut active, &mut level, is_playing),
+                )
                 .changed()
             {
                 grid.set(row, step, active);
+                grid.set_level(row, step, level);
                 do_send_row_activations = true;
             }
         }
 
         // todo replace with +- buttons
-        if ui
-            .add(egui::Slider::new(&mut row_length, 2..=8).text("Row Length"))
-            .changed()
-        {
-            grid.set_row_length(row, row_length);
+        let response = ui.add(
+            egui::Slider::new(&mut row_length, MIN_ROW_LENGTH..=MAX_ROW_LENGTH).text("Row Length"),
+        );
+        if response.changed() {
+            set_row_length(grid, ui_state, tx, row, row_length);
             do_send_row_activations = true;
         }
+        learnable(ui, ui_state, tx, &response, Control::RowLength(row));
+
+        let response = ui
+            .toggle_value(&mut ui_state.row_muted[row], "M")
+            .on_hover_text("Mute the row");
+        if response.changed() {
+            let _ = tx.send(MessageGuiToRho::SetRowMuted {
+                row,
+                muted: ui_state.row_muted[row],
+            });
+        }
+        learnable(ui, ui_state, tx, &response, Control::RowMute(row));
+
+        // each row steps at its own rate against the master clock
+        let mut changed = false;
+        egui::ComboBox::from_id_source(("row_rate", row))
+            .width(60.0)
+            .selected_text(ui_state.row_rates[row].to_string())
+            .show_ui(ui, |ui| {
+                for rate in ROW_RATES {
+                    changed |= ui
+                        .selectable_value(&mut ui_state.row_rates[row], rate, rate.to_string())
+                        .changed();
+                }
+            });
+
+        if changed {
+            let _ = tx.send(MessageGuiToRho::SetRowRate {
+                row,
+                rate: ui_state.row_rates[row],
+            });
+        }
+
+        // gate length and legato for the row
+        let gate_text = match ui_state.row_gates[row] {
+            Some(gate) => format!("Gate {}", gate),
+            None => "Gate".to_string(),
+        };
+        ui.menu_button(gate_text, |ui| {
+            let mut use_global = ui_state.row_gates[row].is_none();
+            if ui.checkbox(&mut use_global, "Global Gate").changed() {
+                ui_state.row_gates[row] = if use_global {
+                    None
+                } else {
+                    Some(ui_state.gate)
+                };
+                let _ = tx.send(MessageGuiToRho::SetRowGate {
+                    row,
+                    gate: ui_state.row_gates[row],
+                });
+            }
+
+            if let Some(gate) = ui_state.row_gates[row].as_mut() {
+                if ui
+                    .horizontal(|ui| gate_editor(ui, ("row_gate", row), gate))
+                    .inner
+                {
+                    let _ = tx.send(MessageGuiToRho::SetRowGate {
+                        row,
+                        gate: Some(*gate),
+                    });
+                }
+            }
+
+            if ui
+                .checkbox(&mut ui_state.row_legato[row], "Legato")
+                .on_hover_text("Hold notes into the next step when it is on")
+                .changed()
+            {
+                let _ = tx.send(MessageGuiToRho::SetRowLegato {
+                    row,
+                    enabled: ui_state.row_legato[row],
+                });
+            }
+        });
+
+        // how hard the row's notes are played
+        let velocity_text = format!("Vel {}", ui_state.row_velocities[row]);
+        ui.menu_button(velocity_text, |ui| {
+            if velocity_editor(ui, &mut ui_state.row_velocities[row]) {
+                let _ = tx.send(MessageGuiToRho::SetRowVelocity {
+                    row,
+                    velocity: ui_state.row_velocities[row],
+                });
+            }
+        });
+
+        // a controller value for each step
+        let lane = &mut ui_state.cc_lanes[row];
+        let cc_text = if lane.enabled {
+            format!("CC {}", lane.cc)
+        } else {
+            "CC".to_string()
+        };
+        ui.menu_button(cc_text, |ui| {
+            lane.resize(grid.row_length(row));
+            if cc_lane_editor(ui, row, lane) {
+                let _ = tx.send(MessageGuiToRho::SetCcLane {
+                    row,
+                    lane: lane.clone(),
+                });
+            }
+        });
+
+        // the ports and channels the row plays on, several of them layer the row
+        ui.menu_button("Out", |ui| {
+            if destinations_editor(
+                ui,
+                row,
+                &mut ui_state.midi.row_destinations[row],
+                &ui_state.ports.outputs,
+            ) {
+                let _ = tx.send(MessageGuiToRho::SetRowDestinations {
+                    row,
+                    destinations: ui_state.midi.row_destinations[row].clone(),
+                });
+            }
+        });
     });
 
     do_send_row_activations
 }
 
-fn top_panel(
-    ctx: &egui::Context,
+// edit a gate length and its units, returns true if it changed
+fn gate_editor(ui: &mut egui::Ui, id_source: impl std::hash::Hash, gate: &mut GateLength) -> bool {
+    let mut changed = match gate {
+        GateLength::Percent(percent) => ui
+            .add(egui::DragValue::new(percent).clamp_range(1.0..=100.0))
+            .changed(),
+        GateLength::Millis(ms) => ui
+            .add(egui::DragValue::new(ms).clamp_range(1.0..=2000.0))
+            .changed(),
+    };
+
+    let is_percent = matches!(gate, GateLength::Percent(_));
+    egui::ComboBox::from_id_source(id_source)
+        .width(40.0)
+        .selected_text(if is_percent { "%" } else { "ms" })
+        .show_ui(ui, |ui| {
+            // switching units starts from the default for the unit
+            if ui.selectable_label(is_percent, "%").clicked() && !is_percent {
+                *gate = GateLength::default();
+                changed = true;
+            }
+            if ui.selectable_label(!is_percent, "ms").clicked() && is_percent {
+                *gate = GateLength::Millis(100.0);
+                changed = true;
+            }
+        });
+
+    changed
+}
+
+// change a row's length, the cc lane keeps a value for each step
+fn set_row_length(
+    grid: &mut GridActivations,
     ui_state: &mut UiState,
-    tx: &std::sync::mpsc::Sender<MessageGuiToRho>,
+    tx: &Sender<MessageGuiToRho>,
+    row: usize,
+    row_length: usize,
 ) {
-    // set up midi list here TODO this happens every frame! Might be slow
-    // could instead use a popup window to set midi ports and if they come and go then we don't care
-    let midi_in = MidiInput::new("midir input").unwrap();
-    let in_ports = midi_in.ports();
-    let mut in_port_names: Vec<String> = in_ports
-        .iter()
-        .map(|port| midi_in.port_name(port).unwrap())
-        .collect();
+    grid.set_row_length(row, row_length);
+    let lane = &mut ui_state.cc_lanes[row];
+    lane.resize(row_length);
+    let _ = tx.send(MessageGuiToRho::SetCcLane {
+        row,
+        lane: lane.clone(),
+    });
+}
 
-    if in_port_names.len() == 0 {
-        in_port_names.push("No Midi In Ports".to_string());
+// pick the interval and direction the empty rows are filled with, returns true if it changed
+fn note_fill_editor(ui: &mut egui::Ui, fill: &mut NoteFill, intervals_text: &mut String) -> bool {
+    let mut changed = false;
+    let custom = parse_intervals(intervals_text).unwrap_or_default();
+    for interval in [
+        FillInterval::Octave,
+        FillInterval::Fifth,
+        FillInterval::Custom(custom),
+    ] {
+        let label = match interval {
+            FillInterval::Custom(_) => "Custom".to_string(),
+            _ => interval.to_string(),
+        };
+        changed |= ui
+            .selectable_value(&mut fill.interval, interval, label)
+            .changed();
+    }
+    // semitones each time round, separated by commas
+    let response = ui.add_enabled(
+        matches!(fill.interval, FillInterval::Custom(_)),
+        egui::TextEdit::singleline(intervals_text).desired_width(80.0),
+    );
+    if response.changed() {
+        if let Some(intervals) = parse_intervals(intervals_text) {
+            fill.interval = FillInterval::Custom(intervals);
+            changed = true;
+        }
+    }
+    ui.separator();
+    for direction in [FillDirection::Up, FillDirection::Down] {
+        changed |= ui
+            .selectable_value(&mut fill.direction, direction, direction.to_string())
+            .changed();
     }
+    changed
+}
 
-    let midi_out = MidiOutput::new("midir output").unwrap();
-    let out_ports = midi_out.ports();
-    // let in_port_name = midi_in.port_name(&in_port)?;
-    let mut out_port_names: Vec<String> = out_ports
+// rows are added and removed at the top, the other rows carry on as they were. New rows start
+// with the default settings, apart from their destinations which are remembered
+fn set_num_rows(
+    grid: &mut GridActivations,
+    ui_state: &mut UiState,
+    tx: &Sender<MessageGuiToRho>,
+    num_rows: usize,
+) {
+    let old_num_rows = grid.num_rows();
+    grid.set_num_rows(num_rows, NEW_ROW_LENGTH);
+    ui_state.playing_steps_for_rows.resize(num_rows, None);
+    ui_state.row_rates.resize(num_rows, RowRate::default());
+    ui_state.row_gates.resize(num_rows, None);
+    ui_state.row_legato.resize(num_rows, false);
+    ui_state
+        .row_velocities
+        .resize(num_rows, VelocityMode::default());
+    ui_state.row_muted.resize(num_rows, false);
+    ui_state.cc_lanes.resize(num_rows, CcLane::default());
+    let destinations = &mut ui_state.midi.row_destinations;
+    if destinations.len() < num_rows {
+        destinations.resize(num_rows, vec![RowDestination::default()]);
+    }
+
+    let _ = tx.send(MessageGuiToRho::SetNumRows { num_rows });
+    for (row, destinations) in destinations
         .iter()
-        .map(|port| midi_out.port_name(port).unwrap())
-        .collect();
+        .enumerate()
+        .take(num_rows)
+        .skip(old_num_rows)
+    {
+        let _ = tx.send(MessageGuiToRho::SetRowDestinations {
+            row,
+            destinations: destinations.clone(),
+        });
+    }
+}
 
-    if out_port_names.len() == 0 {
-        out_port_names.push("No Midi Out Ports".to_string());
+// in learn mode a control is outlined, mapped ones in green and the one being learned in yellow.
+// Clicks go to the outline rather than the control, and pick the control to be learned
+fn learnable(
+    ui: &mut egui::Ui,
+    ui_state: &mut UiState,
+    tx: &Sender<MessageGuiToRho>,
+    response: &egui::Response,
+    control: Control,
+) {
+    if !ui_state.learn_mode {
+        return;
     }
+    let source = ui_state.midi.mappings.source_for(control);
+    let color = if ui_state.learn_target == Some(control) {
+        egui::Color32::YELLOW
+    } else if source.is_some() {
+        egui::Color32::GREEN
+    } else {
+        egui::Color32::GRAY
+    };
+    let rect = response.rect.expand(2.0);
+    ui.painter()
+        .rect_stroke(rect, 2.0, egui::Stroke::new(2.0, color));
 
+    // added after the control so it is on top
+    let overlay = ui.interact(rect, response.id.with("learn"), egui::Sense::click());
+    let hover_text = match source {
+        Some(source) => format!("{}: {}", control, source),
+        None => format!("{}: not mapped", control),
+    };
+    if overlay.on_hover_text(hover_text).clicked() {
+        ui_state.learn_target = Some(control);
+        let _ = tx.send(MessageGuiToRho::LearnControl {
+            control: Some(control),
+        });
+    }
+}
+
+// edit a row's cc lane, returns true if it changed
+fn cc_lane_editor(ui: &mut egui::Ui, row: usize, lane: &mut CcLane) -> bool {
+    let mut changed = ui.checkbox(&mut lane.enabled, "Send CC").changed();
+
+    ui.horizontal(|ui| {
+        ui.label("CC");
+        changed |= ui
+            .add(egui::DragValue::new(&mut lane.cc).clamp_range(0..=127))
+            .changed();
+
+        let channel_text = match lane.channel {
+            Some(channel) => format!("Channel {}", channel),
+            None => "Row Channel".to_string(),
+        };
+        egui::ComboBox::from_id_source(("cc_lane_channel", row))
+            .selected_text(channel_text)
+            .show_ui(ui, |ui| {
+                changed |= ui
+                    .selectable_value(&mut lane.channel, None, "Row Channel")
+                    .changed();
+                for channel in 0..16 {
+                    changed |= ui
+                        .selectable_value(
+                            &mut lane.channel,
+                            Some(channel),
+                            format!("Channel {}", channel),
+                        )
+                        .changed();
+                }
+            });
+    });
+
+    changed |= ui
+        .checkbox(&mut lane.smoothing, "Smooth")
+        .on_hover_text("Glide from each step's value to the next")
+        .changed();
+
+    // a slider for each step
+    ui.horizontal(|ui| {
+        for value in lane.values.iter_mut() {
+            changed |= ui
+                .add(egui::Slider::new(value, 0..=MAX_CC_VALUE).vertical())
+                .changed();
+        }
+    });
+    changed
+}
+
+// choose where a row's velocity comes from, returns true if it changed
+fn velocity_editor(ui: &mut egui::Ui, velocity: &mut VelocityMode) -> bool {
+    let mut changed = false;
+    // fixed starts from the velocity that used to always be sent
+    if ui
+        .radio(*velocity == VelocityMode::Played, "Played")
+        .clicked()
+    {
+        *velocity = VelocityMode::Played;
+        changed = true;
+    }
+    if ui
+        .radio(matches!(velocity, VelocityMode::Fixed(_)), "Fixed")
+        .clicked()
+        && !matches!(velocity, VelocityMode::Fixed(_))
+    {
+        *velocity = VelocityMode::Fixed(100);
+        changed = true;
+    }
+    if ui
+        .radio(matches!(velocity, VelocityMode::Scaled(_)), "Scaled")
+        .clicked()
+        && !matches!(velocity, VelocityMode::Scaled(_))
+    {
+        *velocity = VelocityMode::Scaled(100.0);
+        changed = true;
+    }
+
+    changed |= match velocity {
+        VelocityMode::Played => false,
+        VelocityMode::Fixed(fixed) => ui
+            .add(egui::Slider::new(fixed, 1..=MAX_VELOCITY).text("Velocity"))
+            .changed(),
+        VelocityMode::Scaled(percent) => ui
+            .add(
+                egui::Slider::new(percent, 0.0..=200.0)
+                    .text("Amount")
+                    .suffix("%"),
+            )
+            .changed(),
+    };
+    changed
+}
+
+fn top_panel(ctx: &egui::Context, ui_state: &mut UiState, tx: &Sender<MessageGuiToRho>) {
     egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
         ui.heading("Rho Sequencer");
 
         ui.horizontal(|ui| {
-            let response = egui::ComboBox::from_label("Midi In Port")
-                .selected_text(format!("{:?}", in_port_names[ui_state.selected_in_port]))
-                .show_ui(ui, |ui| {
-                    let mut i = 0;
-                    for port in in_port_names.iter() {
-                        ui.selectable_value(&mut ui_state.selected_in_port, i, port);
-                        i += 1;
-                    }
-                });
+            let midi = &mut ui_state.midi;
 
             // if the midi port selection was changed, send a message to the clock thread
-            if response.response.changed() {
+            if port_combo(
+                ui,
+                "Midi In Port",
+                &mut midi.in_port,
+                &ui_state.ports.inputs,
+            ) {
                 let _ = tx.send(MessageGuiToRho::SetMidiInPort {
-                    port: ui_state.selected_in_port,
+                    port: midi.in_port.clone(),
                 });
             }
 
-            if ui
-                .add(egui::DragValue::new(&mut ui_state.midi_in_channel).clamp_range(0..=15))
-                .changed()
-            {
+            let channel_changed = ui
+                .add_enabled(
+                    !midi.in_omni,
+                    egui::DragValue::new(&mut midi.in_channel).clamp_range(0..=15),
+                )
+                .changed();
+            let omni_changed = ui
+                .checkbox(&mut midi.in_omni, "Omni")
+                .on_hover_text("Play notes from every channel")
+                .changed();
+            if channel_changed || omni_changed {
                 let _ = tx.send(MessageGuiToRho::SetMidiChannelIn {
-                    channel: ui_state.midi_in_channel,
+                    channel: in_channel(midi),
                 });
             }
 
-            let response = egui::ComboBox::from_label("Midi Out Port")
-                .selected_text(format!("{:?}", out_port_names[ui_state.selected_out_port]))
-                .show_ui(ui, |ui| {
-                    let mut i = 0;
-                    for port in out_port_names.iter() {
-                        ui.selectable_value(&mut ui_state.selected_out_port, i, port);
-                        i += 1;
-                    }
-                });
-
-            if response.response.changed() {
+            if port_combo(
+                ui,
+                "Midi Out Port",
+                &mut midi.out_port,
+                &ui_state.ports.outputs,
+            ) {
                 let _ = tx.send(MessageGuiToRho::SetMidiOutPort {
-                    port: ui_state.selected_out_port,
+                    port: midi.out_port.clone(),
                 });
             }
 
             if ui
-                .add(egui::DragValue::new(&mut ui_state.midi_out_channel).clamp_range(0..=15))
+                .add(egui::DragValue::new(&mut midi.out_channel).clamp_range(0..=15))
                 .changed()
             {
                 let _ = tx.send(MessageGuiToRho::SetMidiChannelOut {
-                    channel: ui_state.midi_out_channel,
+                    channel: midi.out_channel,
                 });
             }
+
+            // midi clock is remembered per output port
+            if let Some(port) = midi.out_port.clone() {
+                let mut send_clock = midi.clock_out_ports.contains(&port);
+                if ui.checkbox(&mut send_clock, "Send Clock").changed() {
+                    if send_clock {
+                        midi.clock_out_ports.insert(port.clone());
+                    } else {
+                        midi.clock_out_ports.remove(&port);
+                    }
+                    let _ = tx.send(MessageGuiToRho::SetMidiClockOut {
+                        port,
+                        enabled: send_clock,
+                    });
+                }
+            }
+
+            ui.menu_button("Thru", |ui| {
+                let thru = &mut midi.thru;
+                let mut changed = ui.checkbox(&mut thru.enabled, "Enabled").changed();
+                ui.separator();
+                ui.add_enabled_ui(thru.enabled, |ui| {
+                    changed |= ui.checkbox(&mut thru.notes, "Notes").changed();
+                    changed |= ui
+                        .checkbox(&mut thru.control_change, "Control Change")
+                        .changed();
+                    changed |= ui.checkbox(&mut thru.pitch_bend, "Pitch Bend").changed();
+                    changed |= ui.checkbox(&mut thru.aftertouch, "Aftertouch").changed();
+                    changed |= ui
+                        .checkbox(&mut thru.program_change, "Program Change")
+                        .changed();
+                    ui.separator();
+                    changed |= ui
+                        .checkbox(&mut thru.rechannelize, "Send on Out Channel")
+                        .changed();
+                });
+                if changed {
+                    let _ = tx.send(MessageGuiToRho::SetMidiThru { thru: thru.clone() });
+                }
+            });
         });
 
         ui.add_space(10.0);
 
         // add transport controls
         ui.horizontal(|ui| {
-            if ui.checkbox(&mut ui_state.playing, "Play").clicked() {
-                let _ = tx.send(MessageGuiToRho::SetPlaying { playing: true });
+            let response = ui.checkbox(&mut ui_state.playing, "Play");
+            if response.clicked() {
+                let _ = tx.send(MessageGuiToRho::SetPlaying {
+                    playing: ui_state.playing,
+                });
+            }
+            learnable(ui, ui_state, tx, &response, Control::Play);
+
+            if ui.button("Rewind").clicked() {
+                let _ = tx.send(MessageGuiToRho::Rewind);
             }
 
             if ui
-                .add(egui::Slider::new(&mut ui_state.tempo, 40.0..=1000.0).text("Tempo"))
-                .changed()
+                .button("Panic")
+                .on_hover_text("Stop all notes on every channel")
+                .clicked()
             {
+                let _ = tx.send(MessageGuiToRho::Panic);
+            }
+
+            let response =
+                ui.add(egui::Slider::new(&mut ui_state.tempo, MIN_TEMPO..=MAX_TEMPO).text("Tempo"));
+            if response.changed() {
                 let _ = tx.send(MessageGuiToRho::SetTempo {
                     tempo: ui_state.tempo,
                 });
             }
+            learnable(ui, ui_state, tx, &response, Control::Tempo);
+
+            // tap the button or the T key, as long as nothing is being typed into
+            let tap_key_pressed =
+                !ctx.wants_keyboard_input() && ctx.input(|i| i.key_pressed(egui::Key::T));
+            let tap_clicked = ui.button("Tap").on_hover_text("Or press T").clicked();
+            if tap_key_pressed || tap_clicked {
+                let time = ctx.input(|i| i.time);
+                if let Some(bpm) = ui_state.tap_tempo.tap(time) {
+                    ui_state.tempo = bpm.clamp(MIN_TEMPO, MAX_TEMPO);
+                    let _ = tx.send(MessageGuiToRho::SetTempo {
+                        tempo: ui_state.tempo,
+                    });
+                }
+            }
+
+            let min_swing = MIN_SWING * 100.0;
+            let max_swing = MAX_SWING * 100.0;
+            if ui
+                .add(
+                    egui::Slider::new(&mut ui_state.swing_percent, min_swing..=max_swing)
+                        .text("Swing")
+                        .suffix("%"),
+                )
+                .changed()
+            {
+                let _ = tx.send(MessageGuiToRho::SetSwing {
+                    swing: ui_state.swing_percent / 100.0,
+                });
+            }
+
+            ui.label("Gate");
+            if gate_editor(ui, "gate", &mut ui_state.gate) {
+                let _ = tx.send(MessageGuiToRho::SetGate {
+                    gate: ui_state.gate,
+                });
+            }
+
+            let mut changed = false;
+            egui::ComboBox::from_label("Velocity Curve")
+                .selected_text(ui_state.velocity_curve.to_string())
+                .show_ui(ui, |ui| {
+                    for curve in VELOCITY_CURVES {
+                        changed |= ui
+                            .selectable_value(
+                                &mut ui_state.velocity_curve,
+                                curve,
+                                curve.to_string(),
+                            )
+                            .changed();
+                    }
+                });
+
+            if changed {
+                let _ = tx.send(MessageGuiToRho::SetVelocityCurve {
+                    curve: ui_state.velocity_curve,
+                });
+            }
+
+            let mut changed = false;
+            egui::ComboBox::from_label("Clock")
+                .selected_text(clock_source_name(ui_state.clock_source))
+                .show_ui(ui, |ui| {
+                    for source in [ClockSource::Internal, ClockSource::ExternalMidi] {
+                        changed |= ui
+                            .selectable_value(
+                                &mut ui_state.clock_source,
+                                source,
+                                clock_source_name(source),
+                            )
+                            .changed();
+                    }
+                });
+
+            if changed {
+                let _ = tx.send(MessageGuiToRho::SetClockSource {
+                    source: ui_state.clock_source,
+                });
+            }
+
+            if ui_state.clock_source == ClockSource::ExternalMidi {
+                match ui_state.external_bpm {
+                    Some(bpm) => ui.label(format!("{:.1} BPM", bpm)),
+                    None => ui.label("No Clock"),
+                };
+            }
+        });
+
+        // go to a new tempo gradually
+        ui.horizontal(|ui| {
+            ui.label("Ramp to");
+            ui.add(
+                egui::DragValue::new(&mut ui_state.ramp_tempo)
+                    .clamp_range(MIN_TEMPO..=MAX_TEMPO)
+                    .suffix(" BPM"),
+            );
+            ui.label("over");
+            ui.add(
+                egui::DragValue::new(&mut ui_state.ramp_bars)
+                    .clamp_range(0.0..=64.0)
+                    .speed(0.25)
+                    .suffix(" bars"),
+            );
+            if ui.button("Ramp").clicked() {
+                let _ = tx.send(MessageGuiToRho::RampTempo {
+                    tempo: ui_state.ramp_tempo,
+                    bars: ui_state.ramp_bars,
+                });
+            }
+        });
+
+        // map a controller's knobs and buttons to the controls
+        ui.horizontal(|ui| {
+            if ui
+                .toggle_value(&mut ui_state.learn_mode, "MIDI Learn")
+                .changed()
+                && !ui_state.learn_mode
+            {
+                ui_state.learn_target = None;
+                let _ = tx.send(MessageGuiToRho::LearnControl { control: None });
+            }
+
+            if ui.button("Clear Mappings").clicked() {
+                ui_state.midi.mappings.clear();
+                let _ = tx.send(MessageGuiToRho::SetMidiMappings {
+                    mappings: ui_state.midi.mappings.clone(),
+                });
+            }
+
+            if ui_state.learn_mode {
+                match ui_state.learn_target {
+                    Some(control) => {
+                        ui.label(format!("Move a knob or press a button for {}", control))
+                    }
+                    None => ui.label("Click a control to map it"),
+                };
+            }
         });
 
         ui.add_space(10.0);
     });
 }
+
+// choose a port by name from the available ones. A remembered port that is unplugged stays
+// selected, so we reconnect when it comes back. Returns true if the choice changed
+fn port_combo(
+    ui: &mut egui::Ui,
+    label: &str,
+    port: &mut Option<String>,
+    available: &[String],
+) -> bool {
+    let missing = port.as_ref().filter(|p| !available.contains(p)).cloned();
+    let selected_text = match (&port, &missing) {
+        (_, Some(missing)) => format!("{} (missing)", missing),
+        (Some(port), None) => port.clone(),
+        (None, _) => "None".to_string(),
+    };
+
+    let mut changed = false;
+    egui::ComboBox::from_label(label)
+        .selected_text(selected_text)
+        .show_ui(ui, |ui| {
+            changed |= ui.selectable_value(port, None, "None").changed();
+            for name in available {
+                changed |= ui
+                    .selectable_value(port, Some(name.clone()), name)
+                    .changed();
+            }
+            if let Some(missing) = missing {
+                changed |= ui
+                    .selectable_value(
+                        port,
+                        Some(missing.clone()),
+                        format!("{} (missing)", missing),
+                    )
+                    .changed();
+            }
+        });
+    changed
+}
+
+// edit a row's destinations, returns true if they changed
+fn destinations_editor(
+    ui: &mut egui::Ui,
+    row: usize,
+    destinations: &mut Vec<RowDestination>,
+    available: &[String],
+) -> bool {
+    let mut changed = false;
+    let mut to_remove = None;
+
+    for (i, destination) in destinations.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            let port_text = destination.port.as_deref().unwrap_or("Main Port");
+            egui::ComboBox::from_id_source(("row_out_port", row, i))
+                .selected_text(port_text)
+                .show_ui(ui, |ui| {
+                    changed |= ui
+                        .selectable_value(&mut destination.port, None, "Main Port")
+                        .changed();
+                    for name in available {
+                        changed |= ui
+                            .selectable_value(&mut destination.port, Some(name.clone()), name)
+                            .changed();
+                    }
+                });
+
+            let channel_text = match destination.channel {
+                Some(channel) => format!("Channel {}", channel),
+                None => "Main Channel".to_string(),
+            };
+            egui::ComboBox::from_id_source(("row_out_channel", row, i))
+                .selected_text(channel_text)
+                .show_ui(ui, |ui| {
+                    changed |= ui
+                        .selectable_value(&mut destination.channel, None, "Main Channel")
+                        .changed();
+                    for channel in 0..16 {
+                        changed |= ui
+                            .selectable_value(
+                                &mut destination.channel,
+                                Some(channel),
+                                format!("Channel {}", channel),
+                            )
+                            .changed();
+                    }
+                });
+
+            if ui.button("Remove").clicked() {
+                to_remove = Some(i);
+            }
+        });
+    }
+
+    if let Some(i) = to_remove {
+        destinations.remove(i);
+        changed = true;
+    }
+    if ui.button("Add").clicked() {
+        destinations.push(RowDestination::default());
+        changed = true;
+    }
+    changed
+}
+
+fn in_channel(midi: &MidiSettings) -> Option<u8> {
+    if midi.in_omni {
+        None
+    } else {
+        Some(midi.in_channel)
+    }
+}
+
+// the engine and port state along the bottom of the window, with the last error
+fn status_bar(ctx: &egui::Context, ui_state: &mut UiState) {
+    egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            match &ui_state.status {
+                Some(status) => {
+                    ui.label(if status.playing { "Playing" } else { "Stopped" });
+                    ui.separator();
+                    ui.label(format!("Clock: {}", clock_source_name(status.clock_source)));
+                    ui.separator();
+                    let in_channel = match status.midi_in_channel {
+                        Some(channel) => format!("channel {}", channel),
+                        None => "omni".to_string(),
+                    };
+                    ui.label(format!(
+                        "In: {} {}",
+                        port_state_text(&status.midi_in_port),
+                        in_channel
+                    ));
+                    ui.separator();
+                    ui.label(format!(
+                        "Out: {} channel {}",
+                        port_state_text(&status.midi_out_port),
+                        status.midi_out_channel
+                    ));
+                }
+                None => {
+                    ui.label("Engine not running");
+                }
+            }
+
+            if let Some(error) = &ui_state.error {
+                ui.separator();
+                ui.colored_label(ui.visuals().error_fg_color, error);
+                if ui.small_button("x").on_hover_text("Dismiss").clicked() {
+                    ui_state.error = None;
+                }
+            }
+        });
+    });
+}
+
+fn port_state_text(state: &PortState) -> String {
+    match state {
+        PortState::NotSelected => "not connected".to_string(),
+        PortState::Connected(port) => port.clone(),
+        PortState::Waiting(port) => format!("waiting for {}", port),
+    }
+}
+
+fn clock_source_name(source: ClockSource) -> &'static str {
+    match source {
+        ClockSource::Internal => "Internal",
+        ClockSource::ExternalMidi => "External Midi",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // run a frame of the port combo with its popup open, returning whether the port changed and
+    // where each item's text was drawn
+    fn port_combo_frame(
+        ctx: &egui::Context,
+        events: Vec<egui::Event>,
+        port: &mut Option<String>,
+        available: &[String],
+    ) -> (bool, Vec<(String, egui::Pos2)>) {
+        let input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(800.0, 600.0),
+            )),
+            events,
+            ..Default::default()
+        };
+        let mut changed = false;
+        let output = ctx.run(input, |ctx| {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                let popup_id = ui
+                    .make_persistent_id(egui::Id::new("Midi In"))
+                    .with("popup");
+                ui.memory_mut(|memory| memory.open_popup(popup_id));
+                changed = port_combo(ui, "Midi In", port, available);
+            });
+        });
+        let texts = output
+            .shapes
+            .iter()
+            .filter_map(|clipped| match &clipped.shape {
+                egui::Shape::Text(text) => Some((
+                    text.galley.job.text.clone(),
+                    text.pos + text.galley.rect.center().to_vec2(),
+                )),
+                _ => None,
+            })
+            .collect();
+        (changed, texts)
+    }
+
+    #[test]
+    fn test_port_combo() {
+        let ctx = egui::Context::default();
+        let available = vec!["Keys".to_string(), "Synth".to_string()];
+        let mut port = None;
+
+        // the popup is drawn from the frame after it is opened
+        port_combo_frame(&ctx, vec![], &mut port, &available);
+        let (changed, texts) = port_combo_frame(&ctx, vec![], &mut port, &available);
+        assert!(!changed);
+        // the last one drawn is in the popup rather than the button
+        let (_, synth) = texts
+            .iter()
+            .rev()
+            .find(|(text, _)| text == "Synth")
+            .unwrap();
+
+        let pointer = egui::PointerButton::Primary;
+        let click = vec![
+            egui::Event::PointerMoved(*synth),
+            egui::Event::PointerButton {
+                pos: *synth,
+                button: pointer,
+                pressed: true,
+                modifiers: Default::default(),
+            },
+            egui::Event::PointerButton {
+                pos: *synth,
+                button: pointer,
+                pressed: false,
+                modifiers: Default::default(),
+            },
+        ];
+        let (changed, _) = port_combo_frame(&ctx, click, &mut port, &available);
+        assert!(changed);
+        assert_eq!(port.as_deref(), Some("Synth"));
+
+        // nothing more is picked
+        let (changed, _) = port_combo_frame(&ctx, vec![], &mut port, &available);
+        assert!(!changed);
+    }
+}
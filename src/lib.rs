@@ -1,18 +1,32 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
-use rand;
-
 mod app;
-pub use app::TemplateApp;
-pub mod clock;
+pub use app::RhoApp;
+pub mod cc_lane;
 pub mod clock_runner;
+pub mod engine_clock;
+pub mod external_clock;
+pub mod gate;
 pub mod grid_activations;
 pub mod gui_runner;
 pub mod looping_state;
 pub mod messages;
+pub mod midi_backend;
 pub mod midi_helpers;
+pub mod midi_in;
+pub mod midi_learn;
+pub mod midi_out;
+pub mod midi_thru;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock_midi;
 pub mod note_assigner;
-pub mod phasor;
+pub mod note_fill;
+pub mod port_watcher;
 pub mod rho;
 pub mod rho_config;
+pub mod routing;
+pub mod row_rate;
+pub mod scheduler;
 pub mod step_switch;
+pub mod tap_tempo;
+pub mod velocity;
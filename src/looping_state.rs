@@ -10,10 +10,7 @@ where
     T: Copy,
 {
     pub fn new(data: Vec<T>) -> Self {
-        Self {
-            data: data,
-            counter: 0,
-        }
+        Self { data, counter: 0 }
     }
 
     pub fn clear(&mut self) {
@@ -24,6 +21,25 @@ where
     pub fn reset(&mut self) {
         self.counter = 0;
     }
+
+    // the next call to next() will return this step, wrapping around the length
+    pub fn seek(&mut self, step: usize) {
+        if self.data.is_empty() {
+            self.counter = 0;
+        } else {
+            self.counter = step % self.data.len();
+        }
+    }
+
+    // the value the next call to next() will return, without moving
+    pub fn peek(&self) -> Option<T> {
+        if self.data.is_empty() {
+            None
+        } else {
+            Some(self.data[self.counter % self.data.len()])
+        }
+    }
+
     pub fn append(&mut self, value: T) {
         self.data.push(value);
     }
@@ -35,7 +51,7 @@ where
         }
         // adjust counter to be within bounds
         if self.counter >= new_length {
-            self.counter = self.counter % new_length;
+            self.counter %= new_length;
         }
         self.data.resize(new_length, value);
     }
@@ -56,7 +72,7 @@ where
 
     pub fn get_current_step(&self) -> usize {
         // counter is post incremented so we need to subtract 1
-        if self.data.len() == 0 {
+        if self.data.is_empty() {
             return 0;
         }
         ((self.counter + self.data.len()) - 1) % self.data.len()
@@ -125,4 +141,17 @@ mod tests {
         assert_eq!(s.next(), Some(10));
         assert_eq!(s.next(), Some(10));
     }
+
+    #[test]
+    fn test_seek() {
+        let mut s = LoopingSequence::new(vec![10, 20, 30]);
+
+        s.seek(1);
+        assert_eq!(s.next(), Some(20));
+        s.seek(5);
+        assert_eq!(s.next(), Some(30));
+        assert_eq!(s.next(), Some(10));
+        assert_eq!(s.peek(), Some(20));
+        assert_eq!(s.next(), Some(20));
+    }
 }
@@ -8,19 +8,21 @@ fn main() -> eframe::Result<()> {
 
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
-            .with_inner_size([400.0, 300.0])
-            .with_min_inner_size([300.0, 220.0])
+            .with_inner_size([600.0, 600.0])
+            .with_min_inner_size([400.0, 300.0])
             .with_icon(
                 // NOTE: Adding an icon is optional
                 eframe::icon_data::from_png_bytes(&include_bytes!("../assets/icon-256.png")[..])
                     .unwrap(),
             ),
+        default_theme: eframe::Theme::Dark,
+        follow_system_theme: false,
         ..Default::default()
     };
     eframe::run_native(
-        "Rho Eframe",
+        "Rho Sequencer",
         native_options,
-        Box::new(|cc| Box::new(rho_eframe::TemplateApp::new(cc))),
+        Box::new(|cc| Box::new(rho_eframe::RhoApp::new(cc))),
     )
 }
 
@@ -37,7 +39,7 @@ fn main() {
             .start(
                 "the_canvas_id", // hardcode it
                 web_options,
-                Box::new(|cc| Box::new(rho_eframe::TemplateApp::new(cc))),
+                Box::new(|cc| Box::new(rho_eframe::RhoApp::new(cc))),
             )
             .await
             .expect("failed to start eframe");
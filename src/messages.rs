@@ -1,51 +1,250 @@
 // inter thread messages
 
-use crate::note_assigner::Note;
-use crate::rho_config::NUM_ROWS;
+use crate::cc_lane::CcLane;
+use crate::gate::GateLength;
+use crate::midi_learn::{CcSource, Control, MidiMappings};
+use crate::midi_thru::MidiThru;
+use crate::note_assigner::{Note, NoteOrdering, NoteWrapping, RowAssign};
+use crate::note_fill::NoteFill;
+use crate::port_watcher::MidiPorts;
+use crate::routing::RowDestination;
+use crate::row_rate::RowRate;
+use crate::velocity::{VelocityCurve, VelocityMode};
 
 pub const NOTE_ON_MSG: u8 = 0x90;
 pub const NOTE_OFF_MSG: u8 = 0x80;
+// the release velocity sent with every note off we send
+pub const NOTE_OFF_VELOCITY: u8 = 0x64;
+pub const CONTROL_CHANGE_MSG: u8 = 0xB0;
+pub const POLY_PRESSURE_MSG: u8 = 0xA0;
+pub const PROGRAM_CHANGE_MSG: u8 = 0xC0;
+pub const CHANNEL_PRESSURE_MSG: u8 = 0xD0;
+pub const PITCH_BEND_MSG: u8 = 0xE0;
+pub const ALL_NOTES_OFF_CC: u8 = 123;
+
+// system real time messages
+pub const TIMING_CLOCK_MSG: u8 = 0xF8;
+pub const START_MSG: u8 = 0xFA;
+pub const CONTINUE_MSG: u8 = 0xFB;
+pub const STOP_MSG: u8 = 0xFC;
+pub const SONG_POSITION_MSG: u8 = 0xF2;
+
+// where the step clock comes from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClockSource {
+    Internal,
+    ExternalMidi,
+}
 
 // when notes are recieved, we send them to the rho sequencer via a channel
 pub enum MidiInMessage {
-    NoteOn(u8, u8),
-    NoteOff(u8),
+    // channel, note, velocity
+    NoteOn(u8, u8, u8),
+    // channel, note
+    NoteOff(u8, u8),
+    // channel, controller, value
+    ControlChange(u8, u8, u8),
+    // channel, 14 bit bend with 0x2000 in the middle
+    PitchBend(u8, u16),
+    // channel aftertouch: channel, pressure
+    ChannelPressure(u8, u8),
+    // channel, note, pressure
+    PolyPressure(u8, u8, u8),
+    // channel, program
+    ProgramChange(u8, u8),
+    // timing clock, with the midir timestamp in microseconds
+    Clock(u64),
+    Start,
+    Continue,
+    Stop,
+    // song position pointer in midi beats (sixteenth notes)
+    SongPosition(u16),
+}
+
+impl MidiInMessage {
+    // the channel of a channel message, None for system messages
+    pub fn channel(&self) -> Option<u8> {
+        match *self {
+            MidiInMessage::NoteOn(channel, ..)
+            | MidiInMessage::NoteOff(channel, ..)
+            | MidiInMessage::ControlChange(channel, ..)
+            | MidiInMessage::PitchBend(channel, ..)
+            | MidiInMessage::ChannelPressure(channel, ..)
+            | MidiInMessage::PolyPressure(channel, ..)
+            | MidiInMessage::ProgramChange(channel, ..) => Some(channel),
+            _ => None,
+        }
+    }
 }
 
 // messages from the clock to the gui, to display the state of the sequencer
 pub enum MessageToGui {
-    NotesForRows {
-        notes: [Vec<Note>; NUM_ROWS],
-    },
-    Tick {
-        playing_steps: [Option<usize>; NUM_ROWS],
-    },
+    NotesForRows { notes: Vec<Vec<Note>> },
+    Tick { playing_steps: Vec<Option<usize>> },
+    ExternalTempo { bpm: Option<f32> },
+    // the internal tempo as it changes during a ramp
+    Tempo { tempo: f32 },
+    // something went wrong, but the sequencer carries on
+    Error { message: String },
+    // sent when the state of the engine changes
+    Status { status: EngineStatus },
+    // from the port watcher when devices come and go
+    Ports { ports: MidiPorts },
+    // a controller was moved while learning
+    Learned { source: CcSource, control: Control },
+    // a mapped controller changed something the engine owns
+    Playing { playing: bool },
+    HoldNotes { enabled: bool },
+    RowMuted { row: usize, muted: bool },
+    // a mapped controller moved one of the grid's controls, the grid belongs to the gui
+    GridControl { control: Control, value: u8 },
+}
+
+// the state of a midi connection
+#[derive(Debug, Clone, PartialEq)]
+pub enum PortState {
+    NotSelected,
+    Connected(String),
+    // the port we want isn't there, we connect when it comes back
+    Waiting(String),
+}
+
+// what the engine is doing, shown in the status bar
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineStatus {
+    pub playing: bool,
+    pub clock_source: ClockSource,
+    pub midi_in_port: PortState,
+    pub midi_in_channel: Option<u8>,
+    pub midi_out_port: PortState,
+    pub midi_out_channel: u8,
 }
 
 // messages from the gui to the rho sequencer (clock thread). send when the row activations change
 pub enum MessageGuiToRho {
     RowActivations {
-        row_activations: [Vec<bool>; NUM_ROWS],
+        row_activations: Vec<Vec<bool>>,
+        // how hard each step plays, from 0 to 1
+        row_levels: Vec<Vec<f32>>,
+    },
+    // rows are added or removed at the top, send before the activations for the new rows
+    SetNumRows {
+        num_rows: usize,
+    },
+    SetRowRate {
+        row: usize,
+        rate: RowRate,
+    },
+    SetGate {
+        gate: GateLength,
+    },
+    // None goes back to the global gate length
+    SetRowGate {
+        row: usize,
+        gate: Option<GateLength>,
+    },
+    SetRowLegato {
+        row: usize,
+        enabled: bool,
+    },
+    // a muted row keeps stepping but plays no notes
+    SetRowMuted {
+        row: usize,
+        muted: bool,
+    },
+    SetRowVelocity {
+        row: usize,
+        velocity: VelocityMode,
+    },
+    // shapes the velocities played in
+    SetVelocityCurve {
+        curve: VelocityCurve,
+    },
+    SetCcLane {
+        row: usize,
+        lane: CcLane,
+    },
+    // everywhere the row's notes are sent
+    SetRowDestinations {
+        row: usize,
+        destinations: Vec<RowDestination>,
+    },
+    // how held notes are spread over the rows
+    SetNoteOrdering {
+        ordering: NoteOrdering,
+    },
+    SetNoteWrapping {
+        wrapping: NoteWrapping,
+    },
+    // pin held notes to their rows
+    SetRowAssign {
+        assign: RowAssign,
+    },
+    // the lowest note goes to the top active row
+    SetInvertRows {
+        enabled: bool,
+    },
+    // fill the rows without a note with copies of the held notes
+    SetFillOctaves {
+        enabled: bool,
+        fill: NoteFill,
     },
     HoldNotesEnabled {
         enabled: bool,
     },
+    // ports are chosen by name, None disconnects
     SetMidiInPort {
-        port: usize,
+        port: Option<String>,
     },
     SetMidiOutPort {
-        port: usize,
+        port: Option<String>,
     },
+    // None is omni, notes on every channel are played
     SetMidiChannelIn {
-        channel: u8,
+        channel: Option<u8>,
     },
     SetMidiChannelOut {
         channel: u8,
     },
+    SetMidiClockOut {
+        port: String,
+        enabled: bool,
+    },
+    // what is passed from the midi in to the main output
+    SetMidiThru {
+        thru: MidiThru,
+    },
+    // the next controller moved is mapped to this control, None stops learning
+    LearnControl {
+        control: Option<Control>,
+    },
+    SetMidiMappings {
+        mappings: MidiMappings,
+    },
+    // from the port watcher when devices come and go
+    PortsChanged {
+        ports: MidiPorts,
+    },
     SetPlaying {
         playing: bool,
     },
+    // go back to the start, the next play sends midi start rather than continue
+    Rewind,
+    // stop every note we are playing and send all notes off on every channel
+    Panic,
     SetTempo {
         tempo: f32,
     },
+    // move smoothly to the tempo over a number of bars
+    RampTempo {
+        tempo: f32,
+        bars: f32,
+    },
+    // fraction of each pair of steps taken by the first, 0.5 is straight
+    SetSwing {
+        swing: f32,
+    },
+    SetClockSource {
+        source: ClockSource,
+    },
 }
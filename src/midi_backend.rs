@@ -0,0 +1,65 @@
+// the midi connections the engine uses. midir talks to real devices, and the mock in mock_midi
+// lets the engine run without any
+
+use crate::messages::*;
+use crate::midi_helpers::{get_midi_out_connection, set_up_midi_in_connection, MidiInConnection};
+use midir::MidiOutputConnection;
+use std::error::Error;
+use std::sync::mpsc::Sender;
+
+// an open output connection
+pub trait MidiSink: Send {
+    fn send(&mut self, msg: &[u8]) -> Result<(), Box<dyn Error>>;
+}
+
+// an open input connection, it forwards what it recieves until it is closed
+pub trait MidiSource: Send {
+    fn close(self);
+}
+
+// opens connections to ports by name
+pub trait MidiBackend: Clone + Send + 'static {
+    type Sink: MidiSink;
+    type Source: MidiSource;
+
+    fn connect_out(&self, port_name: &str) -> Result<Self::Sink, Box<dyn Error>>;
+
+    // the messages recieved on the port are sent on tx
+    fn connect_in(
+        &self,
+        port_name: &str,
+        tx: Sender<MidiInMessage>,
+    ) -> Result<Self::Source, Box<dyn Error>>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MidirBackend;
+
+impl MidiBackend for MidirBackend {
+    type Sink = MidiOutputConnection;
+    type Source = MidiInConnection;
+
+    fn connect_out(&self, port_name: &str) -> Result<Self::Sink, Box<dyn Error>> {
+        get_midi_out_connection(port_name)
+    }
+
+    fn connect_in(
+        &self,
+        port_name: &str,
+        tx: Sender<MidiInMessage>,
+    ) -> Result<Self::Source, Box<dyn Error>> {
+        set_up_midi_in_connection(tx, port_name)
+    }
+}
+
+impl MidiSink for MidiOutputConnection {
+    fn send(&mut self, msg: &[u8]) -> Result<(), Box<dyn Error>> {
+        Ok(MidiOutputConnection::send(self, msg)?)
+    }
+}
+
+impl MidiSource for MidiInConnection {
+    fn close(self) {
+        MidiInConnection::close(self);
+    }
+}
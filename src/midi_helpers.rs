@@ -5,12 +5,39 @@ use midir::{Ignore, MidiIO, MidiInput, MidiInputConnection, MidiOutput, MidiOutp
 use std::error::Error;
 use std::sync::mpsc::Sender;
 
+// the midi in connection forwards what it recieves on the channel
+pub type MidiInConnection = MidiInputConnection<Sender<MidiInMessage>>;
+
+// on linux we make our own ports that other apps can connect to, they are listed with the others
+pub const VIRTUAL_IN_PORT_NAME: &str = "Rho In";
+pub const VIRTUAL_OUT_PORT_NAME: &str = "Rho Out";
+// the alsa client the virtual ports belong to
+const VIRTUAL_CLIENT_NAME: &str = "Rho";
+
+// connects to the input port with this name
 pub fn set_up_midi_in_connection(
     tx: Sender<MidiInMessage>,
-) -> Result<MidiInputConnection<Sender<MidiInMessage>>, Box<dyn Error>> {
+    port_name: &str,
+) -> Result<MidiInConnection, Box<dyn Error>> {
+    #[cfg(target_os = "linux")]
+    if port_name == VIRTUAL_IN_PORT_NAME {
+        use midir::os::unix::VirtualInput;
+
+        let mut midi_in = MidiInput::new(VIRTUAL_CLIENT_NAME)?;
+        midi_in.ignore(Ignore::None);
+        let conn_in = midi_in.create_virtual(
+            port_name,
+            move |stamp, message, tx| {
+                on_midi_in(tx, stamp, message);
+            },
+            tx,
+        )?;
+        return Ok(conn_in);
+    }
+
     let mut midi_in = MidiInput::new("midir input")?;
     midi_in.ignore(Ignore::None);
-    let in_port = select_port(&midi_in, "input", 0)?;
+    let in_port = select_port(&midi_in, "input", port_name)?;
 
     let conn_in = midi_in.connect(
         &in_port,
@@ -18,62 +45,229 @@ pub fn set_up_midi_in_connection(
         move |stamp, message, tx| {
             on_midi_in(tx, stamp, message);
         },
-        tx.clone(),
+        tx,
     )?;
     Ok(conn_in)
 }
 
 // when a midi in message is recieved, we call this function
-pub fn on_midi_in(tx: &mut std::sync::mpsc::Sender<MidiInMessage>, _stamp: u64, message: &[u8]) {
+pub fn on_midi_in(tx: &mut std::sync::mpsc::Sender<MidiInMessage>, stamp: u64, message: &[u8]) {
     //println!("{}: {:?} (len = {})", stamp, message, message.len());
 
-    const MSG_NOTE: u8 = 144;
-    const MSG_NOTE_2: u8 = 145;
-    const MSG_NOTE_OFF: u8 = 129;
+    if let Some(msg) = parse_midi_in(stamp, message) {
+        // the receiver only goes away when the app is shutting down
+        let _ = tx.send(msg);
+    }
+}
 
-    let status = message[0];
-    let note = message[1];
-    let velocity = message[2];
+// turn the raw bytes into a message for the sequencer, None for anything we don't handle
+pub fn parse_midi_in(stamp: u64, message: &[u8]) -> Option<MidiInMessage> {
+    let status = *message.first()?;
+    let channel = status & 0x0F;
 
-    if status == MSG_NOTE || status == MSG_NOTE_2 {
-        if velocity > 0 {
-            print!("sending note on {:?}\n", note);
-            tx.send(MidiInMessage::NoteOn(note, velocity)).unwrap(); // TODO this can panic!
-        } else {
-            tx.send(MidiInMessage::NoteOff(note)).unwrap();
+    match status {
+        TIMING_CLOCK_MSG => Some(MidiInMessage::Clock(stamp)),
+        START_MSG => Some(MidiInMessage::Start),
+        CONTINUE_MSG => Some(MidiInMessage::Continue),
+        STOP_MSG => Some(MidiInMessage::Stop),
+        SONG_POSITION_MSG => {
+            let lsb = *message.get(1)? as u16;
+            let msb = *message.get(2)? as u16;
+            Some(MidiInMessage::SongPosition((msb << 7) | lsb))
         }
-    } else if status == MSG_NOTE_OFF {
-        tx.send(MidiInMessage::NoteOff(note)).unwrap();
+        // channel messages have the channel in the bottom four bits
+        _ => match status & 0xF0 {
+            NOTE_ON_MSG => {
+                let note = *message.get(1)?;
+                let velocity = *message.get(2)?;
+                if velocity > 0 {
+                    Some(MidiInMessage::NoteOn(channel, note, velocity))
+                } else {
+                    Some(MidiInMessage::NoteOff(channel, note))
+                }
+            }
+            NOTE_OFF_MSG => Some(MidiInMessage::NoteOff(channel, *message.get(1)?)),
+            CONTROL_CHANGE_MSG => Some(MidiInMessage::ControlChange(
+                channel,
+                *message.get(1)?,
+                *message.get(2)?,
+            )),
+            PITCH_BEND_MSG => {
+                let lsb = *message.get(1)? as u16;
+                let msb = *message.get(2)? as u16;
+                Some(MidiInMessage::PitchBend(channel, (msb << 7) | lsb))
+            }
+            CHANNEL_PRESSURE_MSG => Some(MidiInMessage::ChannelPressure(channel, *message.get(1)?)),
+            POLY_PRESSURE_MSG => Some(MidiInMessage::PolyPressure(
+                channel,
+                *message.get(1)?,
+                *message.get(2)?,
+            )),
+            PROGRAM_CHANGE_MSG => Some(MidiInMessage::ProgramChange(channel, *message.get(1)?)),
+            _ => None,
+        },
     }
 }
 
-pub fn get_midi_out_connection(port_index: usize) -> Result<MidiOutputConnection, Box<dyn Error>> {
-    let midi_out = MidiOutput::new("midir output")?;
+// connects to the output port with this name
+pub fn get_midi_out_connection(port_name: &str) -> Result<MidiOutputConnection, Box<dyn Error>> {
+    #[cfg(target_os = "linux")]
+    if port_name == VIRTUAL_OUT_PORT_NAME {
+        use midir::os::unix::VirtualOutput;
 
-    println!();
-    let out_port = select_port(&midi_out, "output", port_index)?;
+        let midi_out = MidiOutput::new(VIRTUAL_CLIENT_NAME)?;
+        return Ok(midi_out.create_virtual(port_name)?);
+    }
 
-    // let in_port_name = midi_in.port_name(&in_port)?;
-    let out_port_name = midi_out.port_name(&out_port)?;
+    let midi_out = MidiOutput::new("midir output")?;
 
-    let conn_out = midi_out.connect(&out_port, &out_port_name)?;
+    let out_port = select_port(&midi_out, "output", port_name)?;
 
-    // how you use the connection
-    // const NOTE_ON_MSG: u8 = 0x90;
-    // const NOTE_OFF_MSG: u8 = 0x80;
-    // const VELOCITY: u8 = 0x64;
-    // // We're ignoring errors in here
-    // let _ = conn_out.send(&[NOTE_ON_MSG, note, VELOCITY]);
+    let conn_out = midi_out.connect(&out_port, port_name)?;
 
     Ok(conn_out)
 }
 
+// the names of the available ports, empty if midi isn't available
+pub fn input_port_names() -> Vec<String> {
+    let names = MidiInput::new("midir input").map_or(vec![], |midi_in| port_names(&midi_in));
+    with_virtual_port(names, VIRTUAL_IN_PORT_NAME)
+}
+
+pub fn output_port_names() -> Vec<String> {
+    let names = MidiOutput::new("midir output").map_or(vec![], |midi_out| port_names(&midi_out));
+    with_virtual_port(names, VIRTUAL_OUT_PORT_NAME)
+}
+
+// puts our virtual port first where we can make one. our own ports are left out of the list,
+// so we can't be connected back to ourselves
+fn with_virtual_port(names: Vec<String>, virtual_port_name: &str) -> Vec<String> {
+    let own_port_prefix = format!("{}:", VIRTUAL_CLIENT_NAME);
+    let names = names
+        .into_iter()
+        .filter(|name| !name.starts_with(&own_port_prefix));
+    if cfg!(target_os = "linux") {
+        std::iter::once(virtual_port_name.to_string())
+            .chain(names)
+            .collect()
+    } else {
+        names.collect()
+    }
+}
+
+fn port_names<T: MidiIO>(midi_io: &T) -> Vec<String> {
+    midi_io
+        .ports()
+        .iter()
+        .map(|port| {
+            midi_io
+                .port_name(port)
+                .unwrap_or_else(|_| "Unknown Port".to_string())
+        })
+        .collect()
+}
+
+// ports are found by name, their indices change as devices come and go
 pub fn select_port<T: MidiIO>(
     midi_io: &T,
-    _descr: &str,
-    port_index: usize,
+    descr: &str,
+    port_name: &str,
 ) -> Result<T::Port, Box<dyn Error>> {
-    let midi_ports = midi_io.ports();
-    let port = midi_ports.get(port_index).ok_or("Invalid port number")?;
-    Ok(port.clone())
+    let port = midi_io
+        .ports()
+        .into_iter()
+        .find(|port| {
+            midi_io
+                .port_name(port)
+                .map_or(false, |name| name == port_name)
+        })
+        .ok_or_else(|| format!("No midi {} port called {}", descr, port_name))?;
+    Ok(port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_real_time() {
+        assert!(matches!(
+            parse_midi_in(42, &[TIMING_CLOCK_MSG]),
+            Some(MidiInMessage::Clock(42))
+        ));
+        assert!(matches!(
+            parse_midi_in(0, &[START_MSG]),
+            Some(MidiInMessage::Start)
+        ));
+        assert!(matches!(
+            parse_midi_in(0, &[CONTINUE_MSG]),
+            Some(MidiInMessage::Continue)
+        ));
+        assert!(matches!(
+            parse_midi_in(0, &[STOP_MSG]),
+            Some(MidiInMessage::Stop)
+        ));
+    }
+
+    #[test]
+    fn test_parse_song_position() {
+        // 200 = 1 * 128 + 72
+        assert!(matches!(
+            parse_midi_in(0, &[SONG_POSITION_MSG, 72, 1]),
+            Some(MidiInMessage::SongPosition(200))
+        ));
+        // truncated messages are ignored
+        assert!(parse_midi_in(0, &[SONG_POSITION_MSG, 72]).is_none());
+    }
+
+    #[test]
+    fn test_parse_notes() {
+        assert!(matches!(
+            parse_midi_in(0, &[0x90, 60, 100]),
+            Some(MidiInMessage::NoteOn(0, 60, 100))
+        ));
+        assert!(matches!(
+            parse_midi_in(0, &[0x90, 60, 0]),
+            Some(MidiInMessage::NoteOff(0, 60))
+        ));
+        // every channel, not just the first two
+        assert!(matches!(
+            parse_midi_in(0, &[0x9F, 60, 100]),
+            Some(MidiInMessage::NoteOn(15, 60, 100))
+        ));
+        assert!(matches!(
+            parse_midi_in(0, &[0x85, 60, 0]),
+            Some(MidiInMessage::NoteOff(5, 60))
+        ));
+        assert!(matches!(
+            parse_midi_in(0, &[0xB3, 1, 64]),
+            Some(MidiInMessage::ControlChange(3, 1, 64))
+        ));
+        assert!(matches!(
+            parse_midi_in(0, &[0xE1, 0x7F, 0x7F]),
+            Some(MidiInMessage::PitchBend(1, 0x3FFF))
+        ));
+        assert!(matches!(
+            parse_midi_in(0, &[0xC2, 5]),
+            Some(MidiInMessage::ProgramChange(2, 5))
+        ));
+        // truncated messages and ones we don't handle are ignored
+        assert!(parse_midi_in(0, &[0xE0, 0]).is_none());
+        assert!(parse_midi_in(0, &[0xF0, 0x7E, 0xF7]).is_none());
+        assert!(parse_midi_in(0, &[]).is_none());
+    }
+
+    #[test]
+    fn test_with_virtual_port() {
+        let names = vec![
+            "Midi Through:Midi Through Port-0 14:0".to_string(),
+            "Rho:Rho Out 130:0".to_string(),
+        ];
+        let names = with_virtual_port(names, VIRTUAL_OUT_PORT_NAME);
+        if cfg!(target_os = "linux") {
+            assert_eq!(names[0], VIRTUAL_OUT_PORT_NAME);
+        }
+        assert!(names.contains(&"Midi Through:Midi Through Port-0 14:0".to_string()));
+        assert!(!names.contains(&"Rho:Rho Out 130:0".to_string()));
+    }
 }
@@ -0,0 +1,119 @@
+// the midi in connection, which follows the chosen port as devices come and go
+
+use crate::messages::*;
+use crate::midi_backend::{MidiBackend, MidiSource};
+use crate::port_watcher::PortChoice;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::sync::mpsc::Sender;
+
+pub struct MidiIn<B: MidiBackend> {
+    backend: B,
+    // the connection sends what it recieves on this channel
+    tx: Sender<MidiInMessage>,
+    conn: Option<B::Source>,
+    choice: PortChoice,
+}
+
+impl<B: MidiBackend> MidiIn<B> {
+    pub fn new(backend: B, tx: Sender<MidiInMessage>) -> Self {
+        MidiIn {
+            backend,
+            tx,
+            conn: None,
+            choice: PortChoice::new(),
+        }
+    }
+
+    // choose the port to listen to, it is connected straight away if it is available
+    pub fn set_port(
+        &mut self,
+        port: Option<String>,
+        available: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        self.choice.choose(port);
+        self.update_ports(available)
+    }
+
+    // call when the available ports change, to drop ports that have gone and reconnect ones that
+    // have come back
+    pub fn update_ports(&mut self, available: &[String]) -> Result<(), Box<dyn Error>> {
+        if self.choice.should_disconnect(available) {
+            self.close();
+        }
+        if let Some(port) = self.choice.to_connect(available) {
+            self.conn = Some(self.backend.connect_in(&port, self.tx.clone())?);
+            self.choice.set_connected(Some(port));
+        }
+        Ok(())
+    }
+
+    pub fn state(&self) -> PortState {
+        self.choice.state()
+    }
+
+    pub fn port_name(&self) -> Option<&str> {
+        self.choice.connected()
+    }
+
+    pub fn close(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            conn.close();
+        }
+        self.choice.set_connected(None);
+    }
+}
+
+// the keys held down on the midi in, as (channel, note number). If we stop listening to a channel
+// or port its note offs never reach us, so the notes are let go for it
+#[derive(Debug, Default)]
+pub struct HeldNotes {
+    notes: BTreeSet<(u8, u8)>,
+}
+
+impl HeldNotes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn note_on(&mut self, channel: u8, note: u8) {
+        self.notes.insert((channel, note));
+    }
+
+    pub fn note_off(&mut self, channel: u8, note: u8) {
+        self.notes.remove(&(channel, note));
+    }
+
+    // forget the notes on the channels we no longer listen to, returning their note numbers
+    pub fn release(&mut self, listening: impl Fn(u8) -> bool) -> Vec<u8> {
+        let mut released = vec![];
+        self.notes.retain(|&(channel, note)| {
+            if listening(channel) {
+                true
+            } else {
+                released.push(note);
+                false
+            }
+        });
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_held_notes() {
+        let mut held = HeldNotes::new();
+        held.note_on(0, 60);
+        held.note_on(1, 62);
+        held.note_on(1, 64);
+        held.note_off(1, 64);
+
+        assert_eq!(held.release(|channel| channel == 0), vec![62]);
+        assert!(held.release(|channel| channel == 0).is_empty());
+        // a new port is none of the old channels
+        assert_eq!(held.release(|_| false), vec![60]);
+    }
+}
@@ -0,0 +1,187 @@
+// maps the knobs and buttons of a midi controller to the sequencer's controls. A mapping is made
+// by picking a control and then moving the knob that should drive it
+
+use crate::rho_config::{MAX_ROW_LENGTH, MIN_ROW_LENGTH};
+use std::collections::HashMap;
+use std::fmt;
+
+// the tempo range a knob covers
+pub const MIN_CC_TEMPO: f32 = 40.0;
+pub const MAX_CC_TEMPO: f32 = 240.0;
+
+const MAX_CC_VALUE: f32 = 127.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub enum Control {
+    Density,
+    Tempo,
+    Hold,
+    Play,
+    NewDistribution,
+    RowLength(usize),
+    RowMute(usize),
+}
+
+// a controller on a channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub struct CcSource {
+    pub channel: u8,
+    pub cc: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct Mapping {
+    pub source: CcSource,
+    pub control: Control,
+}
+
+// each control is driven by at most one controller, and each controller drives at most one control
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct MidiMappings {
+    mappings: Vec<Mapping>,
+}
+
+impl MidiMappings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // map the controller to the control, replacing what either was mapped to before
+    pub fn learn(&mut self, source: CcSource, control: Control) {
+        self.mappings
+            .retain(|mapping| mapping.source != source && mapping.control != control);
+        self.mappings.push(Mapping { source, control });
+    }
+
+    pub fn control_for(&self, source: CcSource) -> Option<Control> {
+        self.mappings
+            .iter()
+            .find(|mapping| mapping.source == source)
+            .map(|mapping| mapping.control)
+    }
+
+    pub fn source_for(&self, control: Control) -> Option<CcSource> {
+        self.mappings
+            .iter()
+            .find(|mapping| mapping.control == control)
+            .map(|mapping| mapping.source)
+    }
+
+    pub fn clear(&mut self) {
+        self.mappings.clear();
+    }
+}
+
+// buttons act when they are pressed and are ignored when they are let go, so they toggle
+fn is_pressed(value: u8) -> bool {
+    value >= 64
+}
+
+// where each controller was last, so a button acts once each time it is pressed rather than on
+// every value it sends, and a knob mapped to a button only acts when it goes past half way up
+#[derive(Debug, Clone, Default)]
+pub struct ButtonStates {
+    values: HashMap<CcSource, u8>,
+}
+
+impl ButtonStates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // true if the controller has just been pressed. Controllers we haven't heard from start
+    // let go
+    pub fn pressed(&mut self, source: CcSource, value: u8) -> bool {
+        let was_pressed = self.values.insert(source, value).map_or(false, is_pressed);
+        is_pressed(value) && !was_pressed
+    }
+}
+
+pub fn cc_to_tempo(value: u8) -> f32 {
+    MIN_CC_TEMPO + (MAX_CC_TEMPO - MIN_CC_TEMPO) * value as f32 / MAX_CC_VALUE
+}
+
+pub fn cc_to_density(value: u8) -> f32 {
+    value as f32 / MAX_CC_VALUE
+}
+
+pub fn cc_to_row_length(value: u8) -> usize {
+    let range = (MAX_ROW_LENGTH - MIN_ROW_LENGTH) as f32;
+    MIN_ROW_LENGTH + (range * value as f32 / MAX_CC_VALUE).round() as usize
+}
+
+impl fmt::Display for Control {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Control::Density => write!(f, "Density"),
+            Control::Tempo => write!(f, "Tempo"),
+            Control::Hold => write!(f, "Hold"),
+            Control::Play => write!(f, "Play"),
+            Control::NewDistribution => write!(f, "New Dist"),
+            Control::RowLength(row) => write!(f, "Row {} Length", row + 1),
+            Control::RowMute(row) => write!(f, "Row {} Mute", row + 1),
+        }
+    }
+}
+
+impl fmt::Display for CcSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CC {} on channel {}", self.cc, self.channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_learn() {
+        let knob = CcSource { channel: 0, cc: 1 };
+        let other_knob = CcSource { channel: 1, cc: 1 };
+        let mut mappings = MidiMappings::new();
+        assert_eq!(mappings.control_for(knob), None);
+
+        mappings.learn(knob, Control::Tempo);
+        mappings.learn(other_knob, Control::Density);
+        assert_eq!(mappings.control_for(knob), Some(Control::Tempo));
+        assert_eq!(mappings.source_for(Control::Density), Some(other_knob));
+
+        // learning a knob again moves it to the new control
+        mappings.learn(knob, Control::RowMute(2));
+        assert_eq!(mappings.control_for(knob), Some(Control::RowMute(2)));
+        assert_eq!(mappings.source_for(Control::Tempo), None);
+
+        // and a control only follows its latest knob
+        mappings.learn(knob, Control::Density);
+        assert_eq!(mappings.control_for(other_knob), None);
+
+        mappings.clear();
+        assert_eq!(mappings.control_for(knob), None);
+    }
+
+    #[test]
+    fn test_cc_values() {
+        assert_eq!(cc_to_tempo(0), MIN_CC_TEMPO);
+        assert_eq!(cc_to_tempo(127), MAX_CC_TEMPO);
+        assert_eq!(cc_to_row_length(0), MIN_ROW_LENGTH);
+        assert_eq!(cc_to_row_length(127), MAX_ROW_LENGTH);
+    }
+
+    #[test]
+    fn test_button_presses() {
+        let knob = CcSource { channel: 0, cc: 1 };
+        let other_knob = CcSource { channel: 0, cc: 2 };
+        let mut buttons = ButtonStates::new();
+
+        // only going past half way up is a press, however many values are sent on the way
+        let presses: Vec<bool> = [100, 110, 0, 127]
+            .iter()
+            .map(|&value| buttons.pressed(knob, value))
+            .collect();
+        assert_eq!(presses, vec![true, false, false, true]);
+
+        // each controller is followed on its own
+        assert!(buttons.pressed(other_knob, 127));
+        assert!(!buttons.pressed(knob, 127));
+    }
+}
@@ -0,0 +1,325 @@
+// the midi output connection, keeping track of the notes that are sounding on it so they can
+// always be stopped
+
+use crate::messages::*;
+use crate::midi_backend::{MidiBackend, MidiSink};
+use crate::port_watcher::PortChoice;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::error::Error;
+
+// the number of midi channels
+const NUM_CHANNELS: u8 = 16;
+
+// the notes started and not yet stopped, as (channel, note number)
+pub struct SoundingNotes {
+    notes: BTreeSet<(u8, u8)>,
+}
+
+impl SoundingNotes {
+    pub fn new() -> Self {
+        SoundingNotes {
+            notes: BTreeSet::new(),
+        }
+    }
+
+    // call with every message sent, note ons with zero velocity count as note offs
+    pub fn track(&mut self, msg: &[u8]) {
+        let (Some(status), Some(note)) = (msg.first(), msg.get(1)) else {
+            return;
+        };
+        let channel = status & 0x0F;
+        let velocity = msg.get(2).copied().unwrap_or(0);
+        match status & 0xF0 {
+            NOTE_ON_MSG if velocity > 0 => {
+                self.notes.insert((channel, *note));
+            }
+            NOTE_ON_MSG | NOTE_OFF_MSG => {
+                self.notes.remove(&(channel, *note));
+            }
+            _ => (),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    // forget all the notes, returning them so they can be stopped
+    pub fn take_all(&mut self) -> Vec<(u8, u8)> {
+        std::mem::take(&mut self.notes).into_iter().collect()
+    }
+}
+
+impl Default for SoundingNotes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct MidiOut<B: MidiBackend> {
+    backend: B,
+    conn: Option<B::Sink>,
+    choice: PortChoice,
+    sounding_notes: SoundingNotes,
+    // the notes played thru from the keys, they are the player's to stop
+    thru_notes: SoundingNotes,
+    // true after a send fails, until one succeeds, so a broken port is only reported once
+    failing: bool,
+    error: Option<String>,
+}
+
+impl<B: MidiBackend> MidiOut<B> {
+    pub fn new(backend: B) -> Self {
+        MidiOut {
+            backend,
+            conn: None,
+            choice: PortChoice::new(),
+            sounding_notes: SoundingNotes::new(),
+            thru_notes: SoundingNotes::new(),
+            failing: false,
+            error: None,
+        }
+    }
+
+    // the name of the port we are connected to
+    pub fn port_name(&self) -> Option<&str> {
+        self.choice.connected()
+    }
+
+    pub fn state(&self) -> PortState {
+        self.choice.state()
+    }
+
+    // choose the port to send to, it is connected straight away if it is available.
+    // the notes on the old port are stopped first
+    pub fn set_port(
+        &mut self,
+        port: Option<String>,
+        available: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        if port.as_deref() != self.choice.wanted() {
+            self.leave();
+        }
+        self.choice.choose(port);
+        self.update_ports(available)
+    }
+
+    // call when the available ports change, to drop ports that have gone and reconnect ones that
+    // have come back
+    pub fn update_ports(&mut self, available: &[String]) -> Result<(), Box<dyn Error>> {
+        if self.choice.should_disconnect(available) {
+            self.close();
+        }
+        if let Some(port) = self.choice.to_connect(available) {
+            self.conn = Some(self.backend.connect_out(&port)?);
+            self.choice.set_connected(Some(port));
+            self.failing = false;
+        }
+        Ok(())
+    }
+
+    // the notes that were sounding can't be stopped once the port has gone
+    fn close(&mut self) {
+        self.conn = None;
+        self.choice.set_connected(None);
+        self.sounding_notes.take_all();
+        self.thru_notes.take_all();
+    }
+
+    // sends a message if there is a connection, without one the message is dropped.
+    // failures are kept to be reported by take_error
+    pub fn send(&mut self, msg: &[u8]) {
+        if self.send_untracked(msg) {
+            self.sounding_notes.track(msg);
+        }
+    }
+
+    // sends a message played thru from the keys, its notes are left alone by stop_all_notes
+    pub fn send_thru(&mut self, msg: &[u8]) {
+        if self.send_untracked(msg) {
+            self.thru_notes.track(msg);
+        }
+    }
+
+    // returns true if the message was sent
+    fn send_untracked(&mut self, msg: &[u8]) -> bool {
+        let Some(conn) = self.conn.as_mut() else {
+            return false;
+        };
+        match conn.send(msg) {
+            Ok(()) => {
+                self.failing = false;
+                true
+            }
+            Err(e) => {
+                if !self.failing {
+                    let port_name = self.choice.connected().unwrap_or("midi out");
+                    self.error = Some(format!("Could not send to {}: {}", port_name, e));
+                }
+                self.failing = true;
+                false
+            }
+        }
+    }
+
+    // the first error since the last successful send, if it hasn't been taken already
+    pub fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+
+    // sends note offs for everything we are playing
+    pub fn stop_all_notes(&mut self) {
+        for (channel, note) in self.sounding_notes.take_all() {
+            self.send(&[NOTE_OFF_MSG + channel, note, NOTE_OFF_VELOCITY]);
+        }
+    }
+
+    // sends note offs for the notes played thru that are still held
+    pub fn stop_thru_notes(&mut self) {
+        for (channel, note) in self.thru_notes.take_all() {
+            self.send_untracked(&[NOTE_OFF_MSG + channel, note, NOTE_OFF_VELOCITY]);
+        }
+    }
+
+    // stops the notes played thru as well as ours, for when we stop sending to the port
+    fn leave(&mut self) {
+        self.stop_all_notes();
+        self.stop_thru_notes();
+    }
+
+    // stops our notes, then tells every channel to stop all of its notes too
+    pub fn panic(&mut self) {
+        self.stop_all_notes();
+        self.thru_notes.take_all();
+        for channel in 0..NUM_CHANNELS {
+            self.send(&[CONTROL_CHANGE_MSG + channel, ALL_NOTES_OFF_CC, 0]);
+        }
+    }
+}
+
+// an output connection for every port that something is sent to, by port name
+pub struct MidiOuts<B: MidiBackend> {
+    backend: B,
+    outs: BTreeMap<String, MidiOut<B>>,
+}
+
+impl<B: MidiBackend> MidiOuts<B> {
+    pub fn new(backend: B) -> Self {
+        MidiOuts {
+            backend,
+            outs: BTreeMap::new(),
+        }
+    }
+
+    // connect to the ports that are wanted and close the rest, stopping their notes.
+    // returns the errors for the ports that wouldn't open
+    pub fn set_ports(&mut self, ports: &BTreeSet<String>, available: &[String]) -> Vec<String> {
+        self.outs.retain(|port, out| {
+            if !ports.contains(port) {
+                out.leave();
+            }
+            ports.contains(port)
+        });
+
+        let mut errors = vec![];
+        for port in ports {
+            if self.outs.contains_key(port) {
+                continue;
+            }
+            let mut out = MidiOut::new(self.backend.clone());
+            if let Err(e) = out.set_port(Some(port.clone()), available) {
+                errors.push(format!("Could not open midi out port {}: {}", port, e));
+            }
+            self.outs.insert(port.clone(), out);
+        }
+        errors
+    }
+
+    // call when the available ports change, returns the errors for ports that wouldn't reconnect
+    pub fn update_ports(&mut self, available: &[String]) -> Vec<String> {
+        self.outs
+            .iter_mut()
+            .filter_map(|(port, out)| {
+                out.update_ports(available)
+                    .err()
+                    .map(|e| format!("Could not reconnect midi out port {}: {}", port, e))
+            })
+            .collect()
+    }
+
+    // sends to the port if it is connected
+    pub fn send(&mut self, port: &str, msg: &[u8]) {
+        if let Some(out) = self.outs.get_mut(port) {
+            out.send(msg);
+        }
+    }
+
+    // sends what is played thru to the port if it is connected
+    pub fn send_thru(&mut self, port: &str, msg: &[u8]) {
+        if let Some(out) = self.outs.get_mut(port) {
+            out.send_thru(msg);
+        }
+    }
+
+    // sends to each of these ports
+    pub fn send_to_ports(&mut self, ports: &HashSet<String>, msg: &[u8]) {
+        for (port, out) in self.outs.iter_mut() {
+            if ports.contains(port) {
+                out.send(msg);
+            }
+        }
+    }
+
+    pub fn state(&self, port: Option<&str>) -> PortState {
+        match port {
+            Some(port) => self
+                .outs
+                .get(port)
+                .map_or(PortState::Waiting(port.to_string()), MidiOut::state),
+            None => PortState::NotSelected,
+        }
+    }
+
+    pub fn stop_all_notes(&mut self) {
+        self.outs.values_mut().for_each(MidiOut::stop_all_notes);
+    }
+
+    pub fn stop_thru_notes(&mut self) {
+        self.outs.values_mut().for_each(MidiOut::stop_thru_notes);
+    }
+
+    pub fn panic(&mut self) {
+        self.outs.values_mut().for_each(MidiOut::panic);
+    }
+
+    pub fn take_errors(&mut self) -> Vec<String> {
+        self.outs
+            .values_mut()
+            .filter_map(MidiOut::take_error)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sounding_notes() {
+        let mut sounding_notes = SoundingNotes::new();
+
+        sounding_notes.track(&[NOTE_ON_MSG, 60, 100]);
+        sounding_notes.track(&[NOTE_ON_MSG + 1, 60, 100]);
+        sounding_notes.track(&[NOTE_ON_MSG, 64, 100]);
+        // the same note on another channel is a different note
+        sounding_notes.track(&[NOTE_OFF_MSG, 60, 0]);
+        // zero velocity is a note off
+        sounding_notes.track(&[NOTE_ON_MSG, 64, 0]);
+        // other messages are ignored
+        sounding_notes.track(&[TIMING_CLOCK_MSG]);
+        sounding_notes.track(&[CONTROL_CHANGE_MSG, 60, 100]);
+
+        assert_eq!(sounding_notes.take_all(), vec![(1, 60)]);
+        assert!(sounding_notes.is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+// passes what is played into rho on to the main output, so pitch bend, mod wheel, aftertouch and
+// sustain reach the synth behind us. Each type of message can be let through or not
+
+use crate::messages::*;
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct MidiThru {
+    pub enabled: bool,
+    // off by default as the sequencer plays the notes
+    pub notes: bool,
+    pub control_change: bool,
+    pub pitch_bend: bool,
+    // channel and polyphonic
+    pub aftertouch: bool,
+    pub program_change: bool,
+    // send on the output channel rather than the one it came in on
+    pub rechannelize: bool,
+}
+
+impl MidiThru {
+    pub fn new() -> Self {
+        MidiThru {
+            enabled: false,
+            notes: false,
+            control_change: true,
+            pitch_bend: true,
+            aftertouch: true,
+            program_change: true,
+            rechannelize: true,
+        }
+    }
+
+    // the bytes to send on for a message, None if it isn't let through
+    pub fn thru(&self, message: &MidiInMessage, out_channel: u8) -> Option<Vec<u8>> {
+        if !self.enabled {
+            return None;
+        }
+        let channel = |channel: u8| {
+            if self.rechannelize {
+                out_channel
+            } else {
+                channel
+            }
+        };
+        match *message {
+            MidiInMessage::NoteOn(c, note, velocity) if self.notes => {
+                Some(vec![NOTE_ON_MSG + channel(c), note, velocity])
+            }
+            MidiInMessage::NoteOff(c, note) if self.notes => {
+                Some(vec![NOTE_OFF_MSG + channel(c), note, NOTE_OFF_VELOCITY])
+            }
+            MidiInMessage::ControlChange(c, cc, value) if self.control_change => {
+                Some(vec![CONTROL_CHANGE_MSG + channel(c), cc, value])
+            }
+            MidiInMessage::PitchBend(c, bend) if self.pitch_bend => Some(vec![
+                PITCH_BEND_MSG + channel(c),
+                (bend & 0x7F) as u8,
+                (bend >> 7) as u8,
+            ]),
+            MidiInMessage::ChannelPressure(c, pressure) if self.aftertouch => {
+                Some(vec![CHANNEL_PRESSURE_MSG + channel(c), pressure])
+            }
+            MidiInMessage::PolyPressure(c, note, pressure) if self.aftertouch => {
+                Some(vec![POLY_PRESSURE_MSG + channel(c), note, pressure])
+            }
+            MidiInMessage::ProgramChange(c, program) if self.program_change => {
+                Some(vec![PROGRAM_CHANGE_MSG + channel(c), program])
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Default for MidiThru {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thru() {
+        let mut thru = MidiThru::new();
+        let bend = MidiInMessage::PitchBend(3, 0x2000);
+        assert_eq!(thru.thru(&bend, 0), None);
+
+        thru.enabled = true;
+        assert_eq!(thru.thru(&bend, 5), Some(vec![0xE5, 0x00, 0x40]));
+        thru.rechannelize = false;
+        assert_eq!(thru.thru(&bend, 5), Some(vec![0xE3, 0x00, 0x40]));
+
+        // filtered by type
+        let note = MidiInMessage::NoteOn(3, 60, 100);
+        assert_eq!(thru.thru(&note, 5), None);
+        thru.notes = true;
+        assert_eq!(thru.thru(&note, 5), Some(vec![0x93, 60, 100]));
+        thru.pitch_bend = false;
+        assert_eq!(thru.thru(&bend, 5), None);
+        assert_eq!(
+            thru.thru(&MidiInMessage::ChannelPressure(3, 90), 5),
+            Some(vec![0xD3, 90])
+        );
+
+        // clock is never passed on, it has its own settings
+        assert_eq!(thru.thru(&MidiInMessage::Clock(0), 5), None);
+    }
+}
@@ -0,0 +1,180 @@
+// a midi backend that keeps everything in memory, so the engine can be tested without devices.
+// what is sent is recorded with the time it was sent, and tests play messages in by port name
+
+use crate::messages::*;
+use crate::midi_backend::{MidiBackend, MidiSink, MidiSource};
+use crate::midi_helpers::parse_midi_in;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// a message sent to a port, timed from when the mock was made
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentMessage {
+    pub time: Duration,
+    pub port: String,
+    pub bytes: Vec<u8>,
+}
+
+struct MockState {
+    start: Instant,
+    sent: Vec<SentMessage>,
+    // the open input connections, by port name
+    inputs: HashMap<String, Sender<MidiInMessage>>,
+}
+
+// clones share the same recording
+#[derive(Clone)]
+pub struct MockMidi {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockMidi {
+    pub fn new() -> Self {
+        MockMidi {
+            state: Arc::new(Mutex::new(MockState {
+                start: Instant::now(),
+                sent: vec![],
+                inputs: HashMap::new(),
+            })),
+        }
+    }
+
+    // everything sent so far, on every port
+    pub fn sent(&self) -> Vec<SentMessage> {
+        self.state.lock().unwrap().sent.clone()
+    }
+
+    // the bytes sent to one port, in order
+    pub fn sent_to(&self, port: &str) -> Vec<Vec<u8>> {
+        self.sent()
+            .into_iter()
+            .filter(|message| message.port == port)
+            .map(|message| message.bytes)
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.state.lock().unwrap().sent.clear();
+    }
+
+    pub fn is_connected_in(&self, port: &str) -> bool {
+        self.state.lock().unwrap().inputs.contains_key(port)
+    }
+
+    // play a message into an input port, as a device would. returns false if nothing is
+    // listening on the port
+    pub fn receive(&self, port: &str, bytes: &[u8]) -> bool {
+        let stamp = self.state.lock().unwrap().start.elapsed().as_micros() as u64;
+        self.receive_at(port, stamp, bytes)
+    }
+
+    // the same with the time stamp in microseconds given, for messages whose timing matters
+    pub fn receive_at(&self, port: &str, stamp: u64, bytes: &[u8]) -> bool {
+        let state = self.state.lock().unwrap();
+        let Some(tx) = state.inputs.get(port) else {
+            return false;
+        };
+        if let Some(msg) = parse_midi_in(stamp, bytes) {
+            let _ = tx.send(msg);
+        }
+        true
+    }
+}
+
+impl Default for MockMidi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct MockSink {
+    port: String,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MidiSink for MockSink {
+    fn send(&mut self, msg: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut state = self.state.lock().unwrap();
+        let time = state.start.elapsed();
+        state.sent.push(SentMessage {
+            time,
+            port: self.port.clone(),
+            bytes: msg.to_vec(),
+        });
+        Ok(())
+    }
+}
+
+pub struct MockSource {
+    port: String,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MidiSource for MockSource {
+    fn close(self) {
+        self.state.lock().unwrap().inputs.remove(&self.port);
+    }
+}
+
+impl MidiBackend for MockMidi {
+    type Sink = MockSink;
+    type Source = MockSource;
+
+    fn connect_out(&self, port_name: &str) -> Result<Self::Sink, Box<dyn Error>> {
+        Ok(MockSink {
+            port: port_name.to_string(),
+            state: self.state.clone(),
+        })
+    }
+
+    fn connect_in(
+        &self,
+        port_name: &str,
+        tx: Sender<MidiInMessage>,
+    ) -> Result<Self::Source, Box<dyn Error>> {
+        self.state
+            .lock()
+            .unwrap()
+            .inputs
+            .insert(port_name.to_string(), tx);
+        Ok(MockSource {
+            port: port_name.to_string(),
+            state: self.state.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_midi() {
+        let mock = MockMidi::new();
+
+        let mut sink = mock.connect_out("Synth").unwrap();
+        sink.send(&[NOTE_ON_MSG, 60, 100]).unwrap();
+        sink.send(&[NOTE_OFF_MSG, 60, 0]).unwrap();
+        assert_eq!(
+            mock.sent_to("Synth"),
+            vec![vec![NOTE_ON_MSG, 60, 100], vec![NOTE_OFF_MSG, 60, 0]]
+        );
+        assert!(mock.sent()[0].time <= mock.sent()[1].time);
+
+        // nothing is listening until the port is connected
+        assert!(!mock.receive("Keys", &[NOTE_ON_MSG, 60, 100]));
+        let (tx, rx) = std::sync::mpsc::channel();
+        let source = mock.connect_in("Keys", tx).unwrap();
+        assert!(mock.receive("Keys", &[NOTE_ON_MSG + 1, 64, 90]));
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(MidiInMessage::NoteOn(1, 64, 90))
+        ));
+
+        source.close();
+        assert!(!mock.is_connected_in("Keys"));
+    }
+}
@@ -1,10 +1,15 @@
 #![allow(dead_code)]
 
 use crate::looping_state::LoopingSequence;
-use crate::rho_config::NUM_ROWS;
+use crate::note_fill::NoteFill;
+use crate::rho_config::DEFAULT_NUM_ROWS;
+use rand::{thread_rng, Rng};
 use std::cmp::PartialOrd;
 use std::fmt;
 
+// the highest midi note
+const MAX_NOTE_NUMBER: usize = 127;
+
 pub fn to_string(note: &Note) -> String {
     let note_class = note.note_number % 12;
     let note_name = match note_class {
@@ -51,25 +56,81 @@ impl fmt::Display for Note {
 }
 
 // How midi notes are assigned to rows
-enum NoteOrdering {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteOrdering {
+    // new notes take the place of released held notes, otherwise go after the others
     OldestFirst,
     LowestFirst,
+    HighestFirst,
+    // each new note goes somewhere among the others
+    Random,
+    // new notes always go after the others, the gaps left by released held notes close up
+    AsPlayed,
 }
 
+pub const NOTE_ORDERINGS: [NoteOrdering; 5] = [
+    NoteOrdering::OldestFirst,
+    NoteOrdering::LowestFirst,
+    NoteOrdering::HighestFirst,
+    NoteOrdering::Random,
+    NoteOrdering::AsPlayed,
+];
+
 // if held notes are pinned to rows, or if changes in held notes reassign rows dynamically
-enum RowAssign {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowAssign {
     Dynamic,
+    // a note keeps its row until it is released, new notes take the free rows
     Hold,
 }
 
-enum NoteWrapping {
+// where the notes go when there are more of them than rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteWrapping {
+    // the extra notes aren't played
     None,
+    // start again from the first row
     Wrap,
+    // come back down the rows
     Fold,
+    // all go on the top row
     StackHigh,
+    // all go on the bottom row
     StackLow,
 }
 
+pub const NOTE_WRAPPINGS: [NoteWrapping; 5] = [
+    NoteWrapping::None,
+    NoteWrapping::Wrap,
+    NoteWrapping::Fold,
+    NoteWrapping::StackHigh,
+    NoteWrapping::StackLow,
+];
+
+impl fmt::Display for NoteOrdering {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoteOrdering::OldestFirst => write!(f, "Oldest First"),
+            NoteOrdering::LowestFirst => write!(f, "Lowest First"),
+            NoteOrdering::HighestFirst => write!(f, "Highest First"),
+            NoteOrdering::Random => write!(f, "Random"),
+            NoteOrdering::AsPlayed => write!(f, "As Played"),
+        }
+    }
+}
+
+impl fmt::Display for NoteWrapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoteWrapping::None => write!(f, "None"),
+            NoteWrapping::Wrap => write!(f, "Wrap"),
+            NoteWrapping::Fold => write!(f, "Fold"),
+            NoteWrapping::StackHigh => write!(f, "Stack High"),
+            NoteWrapping::StackLow => write!(f, "Stack Low"),
+        }
+    }
+}
+
 // data structure for a single row of the sequencer
 // this could implement an iterator trait, and next does the right things...
 #[derive(Debug)]
@@ -160,7 +221,7 @@ fn map_note_index_to_row_index(
         NoteWrapping::StackHigh => Some(stack_high(note_index, max_row)),
         NoteWrapping::StackLow => Some(stack_low(note_index, max_row)),
         NoteWrapping::None => {
-            if note_index < max_row {
+            if note_index <= max_row {
                 Some(note_index)
             } else {
                 None
@@ -168,37 +229,36 @@ fn map_note_index_to_row_index(
         }
     };
 
-    if let Some(r) = row_index {
-        Some(active_row_indices[r])
-    } else {
-        None
-    }
+    row_index.map(|r| active_row_indices[r])
 }
 
 // This class keeps track of the active notes, assigns notes to rows, and handles which note comes next for a given row.
 // Probably should be renamed to reflect that fact...
 pub struct NoteAssigner {
     active_notes: Vec<Option<Note>>, // the none state means that we have an empty row but others are pinned above it
-    rows: [Row; NUM_ROWS],
+    rows: Vec<Row>,
     note_ordering_mode: NoteOrdering,
     note_wrapping_mode: NoteWrapping,
+    row_assign_mode: RowAssign,
 
     hold_notes_enabled: bool,
     auto_octave_enabled: bool,
+    // how the rows without a note are filled when auto octave is on
+    note_fill: NoteFill,
     invert_rows_enabled: bool,
 }
 
 impl NoteAssigner {
     pub fn new() -> Self {
-        let rows_vec: Vec<Row> = (0..4).map(|_| Row::default()).collect();
-        let rows_array: [Row; 4] = rows_vec.try_into().unwrap();
         NoteAssigner {
             active_notes: vec![],
-            rows: rows_array,
+            rows: (0..DEFAULT_NUM_ROWS).map(|_| Row::default()).collect(),
             note_ordering_mode: NoteOrdering::LowestFirst,
             note_wrapping_mode: NoteWrapping::Fold,
+            row_assign_mode: RowAssign::Dynamic,
             hold_notes_enabled: false,
             auto_octave_enabled: false,
+            note_fill: NoteFill::new(),
             invert_rows_enabled: false,
         }
     }
@@ -206,15 +266,19 @@ impl NoteAssigner {
     // given which rows are active and have assigned notes, return the notes
     pub fn get_next_notes(&mut self, triggered_rows: Vec<usize>) -> Vec<Note> {
         // tick all the rows that are active and appear in triggered rows
-        let mut notes = vec![];
-        for row_index in triggered_rows {
-            if self.row_has_note_and_active(row_index) {
-                if let Some(note) = self.rows[row_index].tick() {
-                    notes.push(note);
-                }
-            }
+        triggered_rows
+            .into_iter()
+            .filter_map(|row_index| self.get_next_note(row_index))
+            .collect()
+    }
+
+    // the next note for a single row, if it is active and has notes
+    pub fn get_next_note(&mut self, row_index: usize) -> Option<Note> {
+        if self.row_has_note_and_active(row_index) {
+            self.rows[row_index].tick()
+        } else {
+            None
         }
-        notes
     }
 
     pub fn note_on(&mut self, note_number: usize, velocity: usize) {
@@ -223,13 +287,28 @@ impl NoteAssigner {
             velocity,
         };
 
-        if !self.fill_empty_note_if_available(new_note) {
+        if self.row_assign_mode == RowAssign::Hold {
+            // notes arrive one at a time so there is nothing to order, they take the first free
+            // row and the others stay where they are
+            if !self.fill_empty_note_if_available(new_note) {
+                self.active_notes.push(Some(new_note));
+            }
+        } else if self.note_ordering_mode == NoteOrdering::AsPlayed {
+            self.active_notes.retain(Option::is_some);
+            self.active_notes.push(Some(new_note));
+        } else if !self.fill_empty_note_if_available(new_note) {
             match self.note_ordering_mode {
-                NoteOrdering::LowestFirst => {
-                    let pos = self
-                        .active_notes
-                        .iter()
-                        .position(|x| x.map_or(false, |x| x > new_note));
+                NoteOrdering::LowestFirst | NoteOrdering::HighestFirst => {
+                    let lowest_first = self.note_ordering_mode == NoteOrdering::LowestFirst;
+                    let pos = self.active_notes.iter().position(|x| {
+                        x.map_or(false, |x| {
+                            if lowest_first {
+                                x > new_note
+                            } else {
+                                x < new_note
+                            }
+                        })
+                    });
 
                     if let Some(pos) = pos {
                         self.active_notes.insert(pos, Some(new_note));
@@ -237,7 +316,11 @@ impl NoteAssigner {
                         self.active_notes.push(Some(new_note));
                     }
                 }
-                NoteOrdering::OldestFirst => {
+                NoteOrdering::Random => {
+                    let pos = thread_rng().gen_range(0..=self.active_notes.len());
+                    self.active_notes.insert(pos, Some(new_note));
+                }
+                NoteOrdering::OldestFirst | NoteOrdering::AsPlayed => {
                     self.active_notes.push(Some(new_note));
                 }
             }
@@ -249,7 +332,8 @@ impl NoteAssigner {
     pub fn note_off(&mut self, note_number: usize) {
         // find the note number and remove it, assume there could be more than one
 
-        if self.hold_notes_enabled {
+        // released notes leave a gap so the notes above keep their rows
+        if self.hold_notes_enabled || self.row_assign_mode == RowAssign::Hold {
             self.active_notes.iter_mut().for_each(|note| {
                 if note
                     .as_ref()
@@ -275,20 +359,72 @@ impl NoteAssigner {
         self.hold_notes_enabled = enabled;
     }
 
+    // the notes that are held are put in the new order, the order they were played in is
+    // only known for new notes
+    pub fn set_note_ordering(&mut self, ordering: NoteOrdering) {
+        self.note_ordering_mode = ordering;
+        let sorted = matches!(
+            ordering,
+            NoteOrdering::LowestFirst | NoteOrdering::HighestFirst
+        );
+        // pinned notes aren't moved
+        if sorted && self.row_assign_mode == RowAssign::Dynamic {
+            // the gaps left by released held notes stay where they are
+            let mut notes: Vec<Note> = self.active_notes.iter().flatten().copied().collect();
+            notes.sort_by_key(|note| note.note_number);
+            if ordering == NoteOrdering::HighestFirst {
+                notes.reverse();
+            }
+            for (slot, note) in self.active_notes.iter_mut().flatten().zip(notes) {
+                *slot = note;
+            }
+        }
+        self.update_note_to_row_mapping();
+    }
+
+    // going back to dynamic closes up the gaps left by released notes
+    pub fn set_row_assign(&mut self, assign: RowAssign) {
+        self.row_assign_mode = assign;
+        if assign == RowAssign::Dynamic && !self.hold_notes_enabled {
+            self.active_notes.retain(Option::is_some);
+        }
+        self.update_note_to_row_mapping();
+    }
+
+    pub fn set_note_wrapping(&mut self, wrapping: NoteWrapping) {
+        self.note_wrapping_mode = wrapping;
+        self.update_note_to_row_mapping();
+    }
+
     pub fn all_active_notes_empty(&self) -> bool {
         self.active_notes.iter().all(Option::is_none)
     }
 
     pub fn row_has_note_and_active(&self, index: usize) -> bool {
-        index < NUM_ROWS && self.rows[index].active && self.rows[index].notes.len() > 0
+        index < self.rows.len() && self.rows[index].active && self.rows[index].notes.len() > 0
     }
 
+    // the notes are only spread over the rows again if the row changed, so the rows keep their
+    // place in their notes
     pub fn set_row_active(&mut self, row_number: usize, active: bool) {
-        if row_number < NUM_ROWS {
-            self.rows[row_number].active = active;
+        if let Some(row) = self.rows.get_mut(row_number) {
+            if row.active != active {
+                row.active = active;
+                self.update_note_to_row_mapping();
+            }
         }
     }
 
+    pub fn num_rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    // new rows start active, the notes are spread over the rows again
+    pub fn set_num_rows(&mut self, num_rows: usize) {
+        self.rows.resize_with(num_rows, Row::default);
+        self.update_note_to_row_mapping();
+    }
+
     pub fn clear_all_note_assignments(&mut self) {
         self.rows.iter_mut().for_each(|row| row.notes.clear());
     }
@@ -308,7 +444,7 @@ impl NoteAssigner {
     }
 
     pub fn row_is_active(&self, index: usize) -> bool {
-        index < NUM_ROWS && self.rows[index].active
+        index < self.rows.len() && self.rows[index].active
     }
 
     // "private" stuff
@@ -329,34 +465,39 @@ impl NoteAssigner {
     fn fill_empty_note_if_available(&mut self, note: Note) -> bool {
         // todo there could be multiple empty rows, in which case we should respect the NoteOrdering
         // perhaps
-        let pos = self.active_notes.iter().position(|n| match n {
-            None => true,
-            Some(_) => false,
-        });
+        let pos = self.active_notes.iter().position(|n| n.is_none());
 
         // if Some(pos) then we found an empty slot
 
         match pos {
             Some(pos) => {
                 self.active_notes[pos] = Some(note);
-                return true;
-            }
-            None => {
-                return false;
+                true
             }
+            None => false,
         }
     }
     pub fn wrap_notes_enabled(&self) -> bool {
-        match self.note_wrapping_mode {
-            NoteWrapping::None => false,
-            _ => true,
-        }
+        !matches!(self.note_wrapping_mode, NoteWrapping::None)
     }
 
     // when anything changes, reassign the notes to the rows
     fn update_note_to_row_mapping(&mut self) {
         self.clear_all_note_assignments();
 
+        // the rows the notes go to in turn, top down when inverted
+        let active_rows = self.active_row_indices();
+        if active_rows.is_empty() {
+            return;
+        }
+        let row_indices: Vec<usize> = if self.invert_rows_enabled {
+            (0..active_rows.len())
+                .map(|index| self.invert_active_row_index(index))
+                .collect()
+        } else {
+            active_rows
+        };
+
         // make a copy of active notes, because we can't borrow self.active_notes to change self.rows
         let active_notes = self.active_notes.clone();
         // loop over active notes
@@ -366,11 +507,8 @@ impl NoteAssigner {
             .iter()
             .enumerate()
             .for_each(|(note_index, note)| {
-                let row_index = map_note_index_to_row_index(
-                    note_index,
-                    &self.active_row_indices(),
-                    &self.note_wrapping_mode,
-                );
+                let row_index =
+                    map_note_index_to_row_index(note_index, &row_indices, &self.note_wrapping_mode);
                 if let Some(r) = row_index {
                     if let Some(n) = note {
                         self.rows[r].add_note(*n);
@@ -379,66 +517,90 @@ impl NoteAssigner {
             });
 
         if self.auto_octave_enabled {
-            self.fill_remaining_rows_with_octaves();
+            self.fill_remaining_rows_with_octaves(&row_indices);
         }
 
         //self.wrap_note_rotation_counters();
     }
 
+    // the first note goes to the top active row rather than the bottom one
+    pub fn set_invert_rows_enabled(&mut self, enabled: bool) {
+        self.invert_rows_enabled = enabled;
+        self.update_note_to_row_mapping();
+    }
+
+    pub fn invert_rows_enabled(&self) -> bool {
+        self.invert_rows_enabled
+    }
+
+    pub fn fill_octaves_enabled(&self) -> bool {
+        self.auto_octave_enabled
+    }
+
     pub fn set_fill_octaves_enabled(&mut self, enabled: bool) {
         self.auto_octave_enabled = enabled;
+        self.update_note_to_row_mapping();
     }
 
-    // this fills any notes that don't have notes with octaves above the ones that do
-    fn fill_remaining_rows_with_octaves(&mut self) {
-        // TODO
+    pub fn set_note_fill(&mut self, fill: NoteFill) {
+        self.note_fill = fill;
+        self.update_note_to_row_mapping();
     }
 
-    fn get_octave_shifted_note_for_index(&self, row_index: usize) -> Note {
-        // TODO this mod might be wrong or weird if rows are deactivated
-        if self.active_notes.is_empty() {
-            return Note {
-                note_number: 0,
-                velocity: 0,
-            };
+    // this fills the active rows that don't have notes with the held notes, shifted by the fill
+    // interval each time round. The rows are taken in the order the notes go to them
+    fn fill_remaining_rows_with_octaves(&mut self, row_indices: &[usize]) {
+        for (index, &row) in row_indices.iter().enumerate() {
+            if self.rows[row].notes.len() > 0 {
+                continue;
+            }
+            if let Some(note) = self.get_octave_shifted_note_for_index(index) {
+                self.rows[row].add_note(note);
+            }
         }
-        let note_to_repeat = self.active_notes[row_index % self.active_notes.len()];
-        let octave = row_index / self.active_notes.len();
+    }
 
-        Note {
-            note_number: note_to_repeat.unwrap().note_number + 12 * octave,
-            velocity: note_to_repeat.unwrap().velocity,
+    // the held note for the row at this position among the active rows, moved once for each time
+    // round the held notes. None if nothing is held or the note would be out of midi range
+    fn get_octave_shifted_note_for_index(&self, row_index: usize) -> Option<Note> {
+        let held_notes: Vec<Note> = self.active_notes.iter().flatten().copied().collect();
+        if held_notes.is_empty() {
+            return None;
+        }
+        let note_to_repeat = held_notes[row_index % held_notes.len()];
+        let pass = row_index / held_notes.len();
+        let transpose = if pass == 0 {
+            0
+        } else {
+            self.note_fill.transpose(pass)?
+        };
+        let note_number = note_to_repeat.note_number as i32 + transpose;
+        if !(0..=MAX_NOTE_NUMBER as i32).contains(&note_number) {
+            return None;
         }
+
+        Some(Note {
+            note_number: note_number as usize,
+            velocity: note_to_repeat.velocity,
+        })
     }
 
-    // return an array of the notes assigned to each row
-    const EMPTY_VEC: Vec<Note> = Vec::new();
-    pub fn get_notes_for_rows(&self) -> [Vec<Note>; NUM_ROWS] {
-        let mut notes: [Vec<Note>; NUM_ROWS] = Default::default();
-        for (i, row) in self.rows.iter().enumerate() {
-            notes[i] = row.notes.clone_data();
-        }
-        notes
+    // return the notes assigned to each row
+    pub fn get_notes_for_rows(&self) -> Vec<Vec<Note>> {
+        self.rows.iter().map(|row| row.notes.clone_data()).collect()
     }
+}
 
-    pub fn print_row_notes(&self) {
-        print!("[");
-        let mut i = 0;
-        for row in self.get_notes_for_rows() {
-            print!("[{} ", i);
-            for note in row {
-                print!("{}, ", note);
-            }
-            i += 1;
-            print!("] ");
-        }
-        print!("]\n");
+impl Default for NoteAssigner {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::note_fill::{FillDirection, FillInterval};
 
     #[test]
     fn it_wraps() {
@@ -551,8 +713,6 @@ mod tests {
 
         // expect the notes from the first two rows
         assert_eq!(notes, vec![note1, note2]);
-
-        ga.print_row_notes();
     }
 
     #[test]
@@ -573,16 +733,236 @@ mod tests {
         assert_eq!(ga.active_row_indices(), vec![1]);
     }
 
-    // #[test]
-    // fn test_fill_octaves() {
-    //     let mut ga = NoteAssigner::new();
+    fn row_note_numbers(ga: &NoteAssigner) -> Vec<Vec<usize>> {
+        ga.get_notes_for_rows()
+            .iter()
+            .map(|notes| notes.iter().map(|note| note.note_number).collect())
+            .collect()
+    }
+
+    fn assigner_with_ordering(ordering: NoteOrdering) -> NoteAssigner {
+        let mut ga = NoteAssigner::new();
+        ga.set_note_ordering(ordering);
+        ga.set_hold_notes_enabled(true);
+        ga.note_on(64, 100);
+        ga.note_on(60, 100);
+        ga.note_on(67, 100);
+        ga
+    }
+
+    #[test]
+    fn test_note_ordering() {
+        let ga = assigner_with_ordering(NoteOrdering::LowestFirst);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![60], vec![64], vec![67], vec![]]
+        );
+
+        let mut ga = assigner_with_ordering(NoteOrdering::HighestFirst);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![67], vec![64], vec![60], vec![]]
+        );
+        // held notes are sorted again when the ordering changes
+        ga.set_note_ordering(NoteOrdering::LowestFirst);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![60], vec![64], vec![67], vec![]]
+        );
+
+        // a new note takes the row of a released held note
+        let mut ga = assigner_with_ordering(NoteOrdering::OldestFirst);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![64], vec![60], vec![67], vec![]]
+        );
+        ga.note_off(60);
+        ga.note_on(62, 100);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![64], vec![62], vec![67], vec![]]
+        );
+
+        // or goes after the others, closing the gap
+        let mut ga = assigner_with_ordering(NoteOrdering::AsPlayed);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![64], vec![60], vec![67], vec![]]
+        );
+        ga.note_off(60);
+        ga.note_on(62, 100);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![64], vec![67], vec![62], vec![]]
+        );
+
+        // every note gets a row, in any order
+        let ga = assigner_with_ordering(NoteOrdering::Random);
+        let mut notes: Vec<usize> = row_note_numbers(&ga).into_iter().flatten().collect();
+        assert!(ga.get_notes_for_rows()[..3]
+            .iter()
+            .all(|row| row.len() == 1));
+        notes.sort();
+        assert_eq!(notes, vec![60, 64, 67]);
+    }
 
-    //     ga.note_on(60, 100);
-    //     ga.fill_remaining_rows_with_octaves();
+    #[test]
+    fn test_pinned_rows() {
+        let mut ga = NoteAssigner::new();
+        ga.set_row_assign(RowAssign::Hold);
+        ga.note_on(64, 100);
+        ga.note_on(60, 100);
+        ga.note_on(67, 100);
+        // in the order they came rather than lowest first
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![64], vec![60], vec![67], vec![]]
+        );
+
+        // releasing a note leaves its row free, the others don't move
+        ga.note_off(64);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![], vec![60], vec![67], vec![]]
+        );
+
+        // and the next note takes the first free row
+        ga.note_on(72, 100);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![72], vec![60], vec![67], vec![]]
+        );
+        ga.note_on(62, 100);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![72], vec![60], vec![67], vec![62]]
+        );
+
+        // back to dynamic the rows above a released note move down
+        ga.set_row_assign(RowAssign::Dynamic);
+        ga.note_off(60);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![72], vec![67], vec![62], vec![]]
+        );
+    }
+
+    #[test]
+    fn test_invert_rows() {
+        let mut ga = NoteAssigner::new();
+        ga.note_on(60, 100);
+        ga.note_on(64, 100);
+        ga.set_invert_rows_enabled(true);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![], vec![], vec![64], vec![60]]
+        );
+
+        // only the active rows are flipped, the lowest note goes to the top active row
+        ga.set_row_active(3, false);
+        ga.set_row_active(1, false);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![64], vec![], vec![60], vec![]]
+        );
+        // the extra note folds back down from the top
+        ga.note_on(67, 100);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![64, 67], vec![], vec![60], vec![]]
+        );
+
+        ga.set_invert_rows_enabled(false);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![60], vec![], vec![64, 67], vec![]]
+        );
+    }
 
-    //     assert_eq!(ga.rows[0].notes.clone_data()[0].note_number, 60);
-    //     assert_eq!(ga.rows[1].notes.clone_data()[0].note_number, 72);
-    //     assert_eq!(ga.rows[2].notes.clone_data()[0].note_number, 84);
-    //     assert_eq!(ga.rows[3].notes.clone_data()[0].note_number, 96);
-    // }
+    #[test]
+    fn test_note_wrapping() {
+        // four notes over two rows
+        let rows_for = |wrapping| {
+            let mut ga = NoteAssigner::new();
+            ga.set_note_wrapping(wrapping);
+            ga.set_row_active(2, false);
+            ga.set_row_active(3, false);
+            for note in [60, 62, 64, 65] {
+                ga.note_on(note, 100);
+            }
+            row_note_numbers(&ga)[..2].to_vec()
+        };
+
+        assert_eq!(rows_for(NoteWrapping::None), vec![vec![60], vec![62]]);
+        assert_eq!(
+            rows_for(NoteWrapping::Wrap),
+            vec![vec![60, 64], vec![62, 65]]
+        );
+        assert_eq!(
+            rows_for(NoteWrapping::Fold),
+            vec![vec![60, 65], vec![62, 64]]
+        );
+        assert_eq!(
+            rows_for(NoteWrapping::StackHigh),
+            vec![vec![60], vec![62, 64, 65]]
+        );
+        assert_eq!(
+            rows_for(NoteWrapping::StackLow),
+            vec![vec![60, 64, 65], vec![62]]
+        );
+    }
+
+    #[test]
+    fn test_fill_octaves() {
+        let mut ga = NoteAssigner::new();
+        ga.set_fill_octaves_enabled(true);
+
+        ga.note_on(60, 100);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![60], vec![72], vec![84], vec![96]]
+        );
+
+        // the held notes take turns, going round again an interval further each time
+        ga.note_on(64, 100);
+        ga.set_note_fill(NoteFill {
+            interval: FillInterval::Fifth,
+            direction: FillDirection::Down,
+        });
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![60], vec![64], vec![53], vec![57]]
+        );
+
+        ga.set_note_fill(NoteFill {
+            interval: FillInterval::Custom(vec![3, 4]),
+            direction: FillDirection::Up,
+        });
+        ga.note_off(64);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![60], vec![63], vec![67], vec![70]]
+        );
+
+        // only active rows are filled, notes out of range are left out
+        ga.set_row_active(1, false);
+        ga.note_off(60);
+        ga.note_on(120, 100);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![120], vec![], vec![123], vec![127]]
+        );
+        ga.set_note_fill(NoteFill::new());
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![120], vec![], vec![], vec![]]
+        );
+
+        ga.set_fill_octaves_enabled(false);
+        ga.set_row_active(1, true);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![120], vec![], vec![], vec![]]
+        );
+    }
 }
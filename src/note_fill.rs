@@ -0,0 +1,119 @@
+// rows left without a note can be filled with copies of the held notes, moved up or down by an
+// interval each time round
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FillInterval {
+    Octave,
+    Fifth,
+    // semitones, each time round moves by the next one in the list
+    Custom(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FillDirection {
+    #[default]
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoteFill {
+    pub interval: FillInterval,
+    pub direction: FillDirection,
+}
+
+impl NoteFill {
+    pub fn new() -> Self {
+        NoteFill {
+            interval: FillInterval::Octave,
+            direction: FillDirection::Up,
+        }
+    }
+
+    // how far the copies are moved on the given time round the held notes, starting from 1.
+    // None if there is no interval to move by
+    pub fn transpose(&self, pass: usize) -> Option<i32> {
+        let intervals: &[u8] = match &self.interval {
+            FillInterval::Octave => &[12],
+            FillInterval::Fifth => &[7],
+            FillInterval::Custom(intervals) => intervals,
+        };
+        if intervals.is_empty() {
+            return None;
+        }
+        let semitones: i32 = intervals.iter().cycle().take(pass).map(|&i| i as i32).sum();
+        match self.direction {
+            FillDirection::Up => Some(semitones),
+            FillDirection::Down => Some(-semitones),
+        }
+    }
+}
+
+impl Default for NoteFill {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// a list of semitones like "7, 5"
+pub fn parse_intervals(text: &str) -> Option<Vec<u8>> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse().ok())
+        .collect()
+}
+
+impl fmt::Display for FillInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FillInterval::Octave => write!(f, "Octave"),
+            FillInterval::Fifth => write!(f, "Fifth"),
+            FillInterval::Custom(intervals) => {
+                let intervals: Vec<String> = intervals.iter().map(u8::to_string).collect();
+                write!(f, "{}", intervals.join(", "))
+            }
+        }
+    }
+}
+
+impl fmt::Display for FillDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FillDirection::Up => write!(f, "Up"),
+            FillDirection::Down => write!(f, "Down"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transpose() {
+        let mut fill = NoteFill::new();
+        assert_eq!(fill.transpose(1), Some(12));
+        assert_eq!(fill.transpose(2), Some(24));
+
+        fill.interval = FillInterval::Fifth;
+        fill.direction = FillDirection::Down;
+        assert_eq!(fill.transpose(2), Some(-14));
+
+        // the list repeats
+        fill.interval = FillInterval::Custom(vec![7, 5]);
+        fill.direction = FillDirection::Up;
+        assert_eq!(fill.transpose(1), Some(7));
+        assert_eq!(fill.transpose(2), Some(12));
+        assert_eq!(fill.transpose(3), Some(19));
+
+        fill.interval = FillInterval::Custom(vec![]);
+        assert_eq!(fill.transpose(1), None);
+
+        assert_eq!(parse_intervals("7, 5"), Some(vec![7, 5]));
+        assert_eq!(parse_intervals(""), Some(vec![]));
+        assert_eq!(parse_intervals("7, up"), None);
+    }
+}
@@ -0,0 +1,150 @@
+// a thread that lists the midi ports every so often, and tells the gui and the clock thread when
+// devices are plugged in or unplugged
+
+use crate::messages::*;
+use crate::midi_helpers::{input_port_names, output_port_names};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how often the ports are listed
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+// how often we check if we should stop, so the app can close quickly
+const SLEEP_INTERVAL: Duration = Duration::from_millis(50);
+
+// the names of the available ports
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MidiPorts {
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+}
+
+impl MidiPorts {
+    pub fn list() -> Self {
+        MidiPorts {
+            inputs: input_port_names(),
+            outputs: output_port_names(),
+        }
+    }
+}
+
+pub fn run_port_watcher(
+    tx_gui: Sender<MessageToGui>,
+    tx_clock: Sender<MessageGuiToRho>,
+    running: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut last_ports: Option<MidiPorts> = None;
+
+        while running.load(Ordering::SeqCst) {
+            let ports = MidiPorts::list();
+            if last_ports.as_ref() != Some(&ports) {
+                let _ = tx_gui.send(MessageToGui::Ports {
+                    ports: ports.clone(),
+                });
+                let _ = tx_clock.send(MessageGuiToRho::PortsChanged {
+                    ports: ports.clone(),
+                });
+                last_ports = Some(ports);
+            }
+
+            let listed = Instant::now();
+            while running.load(Ordering::SeqCst) && listed.elapsed() < WATCH_INTERVAL {
+                thread::sleep(SLEEP_INTERVAL);
+            }
+        }
+    })
+}
+
+// the port we want and the one we are connected to, these differ while a device is unplugged
+#[derive(Debug, Clone, Default)]
+pub struct PortChoice {
+    wanted: Option<String>,
+    connected: Option<String>,
+}
+
+impl PortChoice {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn choose(&mut self, port: Option<String>) {
+        self.wanted = port;
+    }
+
+    pub fn wanted(&self) -> Option<&str> {
+        self.wanted.as_deref()
+    }
+
+    pub fn connected(&self) -> Option<&str> {
+        self.connected.as_deref()
+    }
+
+    pub fn set_connected(&mut self, port: Option<String>) {
+        self.connected = port;
+    }
+
+    // the port to connect to, if we want one that is available and aren't already connected
+    pub fn to_connect(&self, available: &[String]) -> Option<String> {
+        let wanted = self.wanted.as_ref()?;
+        if self.connected.as_ref() == Some(wanted) || !available.contains(wanted) {
+            return None;
+        }
+        Some(wanted.clone())
+    }
+
+    // true if the port we are connected to has gone away, or isn't the one we want any more
+    pub fn should_disconnect(&self, available: &[String]) -> bool {
+        match &self.connected {
+            Some(connected) => {
+                !available.contains(connected) || self.wanted.as_ref() != Some(connected)
+            }
+            None => false,
+        }
+    }
+
+    pub fn state(&self) -> PortState {
+        match (&self.wanted, &self.connected) {
+            (_, Some(connected)) => PortState::Connected(connected.clone()),
+            (Some(wanted), None) => PortState::Waiting(wanted.clone()),
+            (None, None) => PortState::NotSelected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_choice() {
+        let synth = "Synth".to_string();
+        let mut choice = PortChoice::new();
+        assert_eq!(choice.state(), PortState::NotSelected);
+
+        // the synth isn't plugged in yet
+        choice.choose(Some(synth.clone()));
+        assert_eq!(choice.to_connect(&[]), None);
+        assert_eq!(choice.state(), PortState::Waiting(synth.clone()));
+
+        // it appears, at whatever index
+        let available = vec!["Other".to_string(), synth.clone()];
+        assert_eq!(choice.to_connect(&available), Some(synth.clone()));
+        choice.set_connected(Some(synth.clone()));
+        assert_eq!(choice.to_connect(&available), None);
+        assert!(!choice.should_disconnect(&available));
+        assert_eq!(choice.state(), PortState::Connected(synth.clone()));
+
+        // unplugged
+        assert!(choice.should_disconnect(&["Other".to_string()]));
+        choice.set_connected(None);
+        assert_eq!(choice.state(), PortState::Waiting(synth.clone()));
+
+        // choosing another port drops the old one
+        choice.set_connected(Some(synth.clone()));
+        choice.choose(Some("Other".to_string()));
+        assert!(choice.should_disconnect(&available));
+    }
+}
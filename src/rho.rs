@@ -1,101 +1,414 @@
+use crate::cc_lane::CcLane;
+use crate::gate::{GateLength, NoteEnd};
+use crate::grid_activations::DEFAULT_STEP_LEVEL;
 use crate::looping_state;
-use crate::note_assigner;
 use crate::note_assigner::Note;
-use crate::note_assigner::NoteAssigner;
-use crate::rho_config::NUM_ROWS;
+use crate::note_assigner::{NoteAssigner, NoteOrdering, NoteWrapping, RowAssign};
+use crate::note_fill::NoteFill;
+use crate::rho_config::DEFAULT_NUM_ROWS;
+use crate::row_rate::RowRate;
+use crate::velocity::{apply_level, VelocityCurve, VelocityMode};
+use std::time::Duration;
 
-pub type Rows = [looping_state::LoopingSequence<bool>; NUM_ROWS];
+pub type Rows = Vec<looping_state::LoopingSequence<bool>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoteEvent {
+    On {
+        row: usize,
+        note: Note,
+        velocity: u8,
+    },
+    Off {
+        row: usize,
+        note: Note,
+    },
+}
+
+// a controller value for a row's cc lane, None for the channel sends on the row's channels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CcEvent {
+    pub row: usize,
+    pub channel: Option<u8>,
+    pub cc: u8,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PlayingNote {
+    note: Note,
+    row: usize,
+    end: NoteEnd,
+}
+
+impl PlayingNote {
+    fn note_off(&self) -> NoteEvent {
+        NoteEvent::Off {
+            row: self.row,
+            note: self.note,
+        }
+    }
+}
 
 pub struct Rho {
     note_assigner: NoteAssigner,
     row_loopers: Rows,
-    playing_notes: Vec<Note>,
+    // how hard each step plays, as a share of the row's velocity
+    row_levels: Vec<Vec<f32>>,
+    row_rates: Vec<RowRate>,
+    gate: GateLength,
+    // rows with their own gate length, None uses the global one
+    row_gates: Vec<Option<GateLength>>,
+    // legato rows hold their note into the next step when it is active
+    row_legato: Vec<bool>,
+    row_velocities: Vec<VelocityMode>,
+    row_muted: Vec<bool>,
+    velocity_curve: VelocityCurve,
+    // the notes that are sounding and when they should stop
+    playing_notes: Vec<PlayingNote>,
+    cc_lanes: Vec<CcLane>,
+    // the last value each lane sent, so smoothing only sends changes
+    sent_cc_values: Vec<Option<u8>>,
 }
 
 impl Rho {
     pub fn new() -> Self {
-        Rho {
+        let mut rho = Rho {
             note_assigner: NoteAssigner::new(),
-            row_loopers: Default::default(),
+            row_loopers: vec![],
+            row_levels: vec![],
+            row_rates: vec![],
+            gate: GateLength::default(),
+            row_gates: vec![],
+            row_legato: vec![],
+            row_velocities: vec![],
+            row_muted: vec![],
+            velocity_curve: VelocityCurve::default(),
             playing_notes: vec![],
-        }
+            cc_lanes: vec![],
+            sent_cc_values: vec![],
+        };
+        rho.set_num_rows(DEFAULT_NUM_ROWS);
+        rho
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.row_loopers.len()
+    }
+
+    // add or remove rows at the top, the rows that stay keep their settings. Returns the note
+    // offs for the notes the removed rows were playing
+    pub fn set_num_rows(&mut self, num_rows: usize) -> Vec<NoteEvent> {
+        self.note_assigner.set_num_rows(num_rows);
+        self.row_loopers.resize(num_rows, Default::default());
+        self.row_levels.resize(num_rows, vec![]);
+        self.row_rates.resize(num_rows, RowRate::default());
+        self.row_gates.resize(num_rows, None);
+        self.row_legato.resize(num_rows, false);
+        self.row_velocities
+            .resize(num_rows, VelocityMode::default());
+        self.row_muted.resize(num_rows, false);
+        self.cc_lanes.resize(num_rows, CcLane::default());
+        self.sent_cc_values.resize(num_rows, None);
+        self.update_active_rows();
+        self.remove_playing_notes(|playing| playing.row >= num_rows)
+            .iter()
+            .map(PlayingNote::note_off)
+            .collect()
     }
 
     pub fn set_fill_octaves_enabled(&mut self, enabled: bool) {
         self.note_assigner.set_fill_octaves_enabled(enabled);
+        self.update_active_rows();
+    }
+
+    pub fn set_note_fill(&mut self, fill: NoteFill) {
+        self.note_assigner.set_note_fill(fill);
     }
 
-    pub fn set_row_activations(&mut self, row_activations: [Vec<bool>; NUM_ROWS]) {
-        for i in 0..NUM_ROWS {
+    pub fn set_row_activations(&mut self, row_activations: Vec<Vec<bool>>) {
+        for (row_looper, activations) in self.row_loopers.iter_mut().zip(row_activations.iter()) {
             // if the length changes, we need to resize the row looper
-            if self.row_loopers[i].len() != row_activations[i].len() {
-                self.row_loopers[i].resize(row_activations[i].len(), false);
+            if row_looper.len() != activations.len() {
+                row_looper.resize(activations.len(), false);
             }
             // set each step
-            for (j, active) in row_activations[i].iter().enumerate() {
-                self.row_loopers[i].set_step(j, *active);
+            for (j, active) in activations.iter().enumerate() {
+                row_looper.set_step(j, *active);
             }
         }
+        self.update_active_rows();
+    }
+
+    // inverting and filling only go over the rows that play, so there a row with no steps on is
+    // inactive. Otherwise every row takes notes, and pinned notes don't move when a row is
+    // emptied. Muted rows stay active so they keep their notes for when they come back
+    fn update_active_rows(&mut self) {
+        let by_steps =
+            self.note_assigner.invert_rows_enabled() || self.note_assigner.fill_octaves_enabled();
+        for (row, row_looper) in self.row_loopers.iter().enumerate() {
+            let active = !by_steps || row_looper.clone_data().contains(&true);
+            self.note_assigner.set_row_active(row, active);
+        }
+    }
+
+    pub fn set_row_levels(&mut self, row_levels: Vec<Vec<f32>>) {
+        for (levels, new_levels) in self.row_levels.iter_mut().zip(row_levels) {
+            *levels = new_levels;
+        }
+    }
+
+    // the level of the step the row has just played, steps without one play at full level
+    fn step_level(&self, row: usize) -> f32 {
+        let step = self.row_loopers[row].get_current_step();
+        self.row_levels[row]
+            .get(step)
+            .copied()
+            .unwrap_or(DEFAULT_STEP_LEVEL)
+    }
+
+    // go back to the first step of every row
+    pub fn reset(&mut self) {
+        self.row_loopers.iter_mut().for_each(|row| row.reset());
+    }
+
+    // move every row to where it would be at this tick, if the tick is part way through a step
+    // the row moves to the following step
+    pub fn set_position(&mut self, tick: u64) {
+        for (row, rate) in self.row_loopers.iter_mut().zip(self.row_rates.iter()) {
+            let ticks_per_step = rate.ticks_per_step() as u64;
+            let step = (tick + ticks_per_step - 1) / ticks_per_step;
+            row.seek(step as usize);
+        }
+    }
+
+    pub fn set_row_rate(&mut self, row: usize, rate: RowRate) {
+        if row < self.num_rows() {
+            self.row_rates[row] = rate;
+        }
+    }
+
+    pub fn set_gate(&mut self, gate: GateLength) {
+        self.gate = gate;
+    }
+
+    pub fn set_row_gate(&mut self, row: usize, gate: Option<GateLength>) {
+        if row < self.num_rows() {
+            self.row_gates[row] = gate;
+        }
+    }
+
+    pub fn set_row_legato(&mut self, row: usize, enabled: bool) {
+        if row < self.num_rows() {
+            self.row_legato[row] = enabled;
+        }
+    }
+
+    pub fn set_row_velocity(&mut self, row: usize, velocity: VelocityMode) {
+        if row < self.num_rows() {
+            self.row_velocities[row] = velocity;
+        }
+    }
+
+    pub fn set_row_muted(&mut self, row: usize, muted: bool) {
+        if row < self.num_rows() {
+            self.row_muted[row] = muted;
+        }
+    }
+
+    pub fn row_muted(&self, row: usize) -> bool {
+        self.row_muted.get(row).copied().unwrap_or(false)
+    }
+
+    pub fn set_velocity_curve(&mut self, curve: VelocityCurve) {
+        self.velocity_curve = curve;
+    }
+
+    pub fn set_cc_lane(&mut self, row: usize, lane: CcLane) {
+        if row < self.num_rows() {
+            self.cc_lanes[row] = lane;
+            self.sent_cc_values[row] = None;
+        }
     }
 
     pub fn set_hold_notes_enabled(&mut self, enabled: bool) {
         self.note_assigner.set_hold_notes_enabled(enabled);
     }
 
+    pub fn set_note_ordering(&mut self, ordering: NoteOrdering) {
+        self.note_assigner.set_note_ordering(ordering);
+    }
+
+    pub fn set_note_wrapping(&mut self, wrapping: NoteWrapping) {
+        self.note_assigner.set_note_wrapping(wrapping);
+    }
+
+    pub fn set_row_assign(&mut self, assign: RowAssign) {
+        self.note_assigner.set_row_assign(assign);
+    }
+
+    pub fn set_invert_rows_enabled(&mut self, enabled: bool) {
+        self.note_assigner.set_invert_rows_enabled(enabled);
+        self.update_active_rows();
+    }
+
     pub fn note_on(&mut self, note: usize, velocity: usize) {
         self.note_assigner.note_on(note, velocity);
-        self.note_assigner.print_row_notes();
     }
 
     pub fn note_off(&mut self, note: usize) {
         self.note_assigner.note_off(note);
-        self.note_assigner.print_row_notes();
     }
 
-    pub fn get_notes_for_rows(&self) -> [Vec<Note>; NUM_ROWS] {
+    pub fn get_notes_for_rows(&self) -> Vec<Vec<Note>> {
         self.note_assigner.get_notes_for_rows()
     }
 
-    pub fn on_clock_high(&mut self) -> Vec<note_assigner::Note> {
+    // call on every clock tick at the time it happened. Stops the notes that have finished,
+    // steps the rows that are due and returns the note ons and offs in the order to send them
+    pub fn on_tick(&mut self, tick: u64, now: Duration) -> Vec<NoteEvent> {
+        // finished notes go first so a note that is played again isn't cut short
+        let mut events: Vec<NoteEvent> = self
+            .remove_playing_notes(|playing| match playing.end {
+                NoteEnd::Tick(off_tick) => off_tick <= tick,
+                NoteEnd::Time(off_time) => off_time <= now,
+                NoteEnd::NextStep => false,
+            })
+            .iter()
+            .map(PlayingNote::note_off)
+            .collect();
+
+        let due_rows: Vec<usize> = (0..self.num_rows())
+            .filter(|row| self.row_is_due(*row, tick))
+            .collect();
         // get the rows that are triggered by ticking the row loopers
-        let triggered_rows = self.tick_rows();
+        let triggered_rows = self.tick_rows(tick);
 
-        let notes_to_play = self.note_assigner.get_next_notes(triggered_rows);
+        for row in due_rows {
+            let held_note = self
+                .remove_playing_notes(|playing| {
+                    playing.row == row && playing.end == NoteEnd::NextStep
+                })
+                .first()
+                .map(|playing| playing.note);
+            let note = if triggered_rows.contains(&row) && !self.row_muted[row] {
+                self.note_assigner.get_next_note(row)
+            } else {
+                None
+            };
 
-        self.track_midi_notes(notes_to_play.clone());
+            if let Some(note) = note {
+                let end = self.note_end(row, tick, now);
+                self.playing_notes.push(PlayingNote { note, row, end });
+                // a tied note carries on rather than being played again
+                if held_note == Some(note) {
+                    continue;
+                }
+                let played = note.velocity.min(u8::MAX as usize) as u8;
+                let velocity = self.row_velocities[row].velocity(played, self.velocity_curve);
+                let velocity = apply_level(velocity, self.step_level(row));
+                events.push(NoteEvent::On {
+                    row,
+                    note,
+                    velocity,
+                });
+            }
+            // stop a held note after the next one starts, so they overlap
+            if let Some(note) = held_note {
+                events.push(NoteEvent::Off { row, note });
+            }
+        }
+        events
+    }
+
+    // call after on_tick for the same tick. Each lane sends its value at the start of every step,
+    // smoothed lanes also send the values in between as they change
+    pub fn cc_events(&mut self, tick: u64) -> Vec<CcEvent> {
+        let mut events = vec![];
+        for row in 0..self.num_rows() {
+            let lane = &self.cc_lanes[row];
+            if !lane.enabled || self.row_loopers[row].len() == 0 {
+                continue;
+            }
+            let ticks_per_step = self.row_rates[row].ticks_per_step() as u64;
+            let step_start = self.row_is_due(row, tick);
+            let fraction = (tick % ticks_per_step) as f32 / ticks_per_step as f32;
+            let step = self.row_loopers[row].get_current_step();
+            let Some(value) = lane.value(step, fraction) else {
+                continue;
+            };
+            if step_start || self.sent_cc_values[row] != Some(value) {
+                self.sent_cc_values[row] = Some(value);
+                events.push(CcEvent {
+                    row,
+                    channel: lane.channel,
+                    cc: lane.cc,
+                    value,
+                });
+            }
+        }
+        events
+    }
 
-        // keep track of the midi notes
-        notes_to_play
+    // returns the notes with a gate in milliseconds that have finished by now,
+    // call between ticks as these don't line up with the clock
+    pub fn notes_to_stop(&mut self, now: Duration) -> Vec<NoteEvent> {
+        self.remove_playing_notes(
+            |playing| matches!(playing.end, NoteEnd::Time(off_time) if off_time <= now),
+        )
+        .iter()
+        .map(PlayingNote::note_off)
+        .collect()
     }
 
-    pub fn on_clock_low(&mut self) -> Vec<note_assigner::Note> {
-        // send note offs for all the notes
-        let notes_to_stop = self.playing_notes.clone();
+    // when the next note with a gate in milliseconds should stop
+    pub fn next_note_off_time(&self) -> Option<Duration> {
+        self.playing_notes
+            .iter()
+            .filter_map(|playing| match playing.end {
+                NoteEnd::Time(off_time) => Some(off_time),
+                _ => None,
+            })
+            .min()
+    }
+
+    // forget the playing notes, whoever sent them has to stop them
+    pub fn clear_playing_notes(&mut self) {
         self.playing_notes.clear();
-        notes_to_stop
     }
 
-    pub fn get_playing_steps(&self) -> [Option<usize>; NUM_ROWS] {
-        let mut steps = [None; NUM_ROWS];
-        let mut i = 0;
-        for row in self.row_loopers.iter() {
-            if self.note_assigner.row_is_active(i) {
-                steps[i] = Some(row.get_current_step());
-            }
-            i += 1;
-        }
-        steps
+    // the notes the row is playing right now
+    pub fn playing_notes_on_row(&self, row: usize) -> Vec<Note> {
+        self.playing_notes
+            .iter()
+            .filter(|playing| playing.row == row)
+            .map(|playing| playing.note)
+            .collect()
     }
 
-    fn track_midi_notes(&mut self, notes: Vec<note_assigner::Note>) {
-        for note in notes {
-            self.playing_notes.push(note);
-        }
+    pub fn get_playing_steps(&self) -> Vec<Option<usize>> {
+        self.row_loopers
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                self.note_assigner
+                    .row_is_active(i)
+                    .then(|| row.get_current_step())
+            })
+            .collect()
     }
 
-    fn tick_rows(&mut self) -> Vec<usize> {
+    // rows step when the tick is a multiple of their step length
+    fn row_is_due(&self, row: usize, tick: u64) -> bool {
+        tick % self.row_rates[row].ticks_per_step() as u64 == 0
+    }
+
+    fn tick_rows(&mut self, tick: u64) -> Vec<usize> {
         let mut triggered_rows = vec![];
-        for i in 0..NUM_ROWS {
+        for i in 0..self.num_rows() {
+            if !self.row_is_due(i, tick) {
+                continue;
+            }
             if let Some(t) = self.row_loopers[i].next() {
                 if t {
                     triggered_rows.push(i);
@@ -104,12 +417,65 @@ impl Rho {
         }
         triggered_rows
     }
+
+    // a legato row holds on until its next step if that is going to play, otherwise the gate
+    // length decides
+    fn note_end(&self, row: usize, tick: u64, now: Duration) -> NoteEnd {
+        if self.row_legato[row] && self.row_loopers[row].peek() == Some(true) {
+            return NoteEnd::NextStep;
+        }
+        let gate = self.row_gates[row].unwrap_or(self.gate);
+        gate.note_end(tick, now, self.row_rates[row].ticks_per_step())
+    }
+
+    fn remove_playing_notes(
+        &mut self,
+        should_stop: impl Fn(&PlayingNote) -> bool,
+    ) -> Vec<PlayingNote> {
+        let mut removed = vec![];
+        self.playing_notes.retain(|playing| {
+            if should_stop(playing) {
+                removed.push(*playing);
+                false
+            } else {
+                true
+            }
+        });
+        removed
+    }
+}
+
+impl Default for Rho {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 // todo test this
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rho_config::PULSES_PER_STEP;
+
+    fn note_ons(events: &[NoteEvent]) -> Vec<Note> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                NoteEvent::On { note, .. } => Some(*note),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn note_offs(events: &[NoteEvent]) -> Vec<Note> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                NoteEvent::Off { note, .. } => Some(*note),
+                _ => None,
+            })
+            .collect()
+    }
 
     #[test]
     fn test_tick_rows() {
@@ -121,23 +487,315 @@ mod tests {
         rho.note_on(2, 100);
         rho.note_on(3, 100);
 
-        let two_true = looping_state::LoopingSequence::new(vec![true, true]);
-        let rows = [
-            two_true.clone(),
-            two_true.clone(),
-            two_true.clone(),
-            two_true.clone(),
-        ];
+        rho.set_row_activations(vec![vec![true, true]; 4]);
 
-        rho.row_loopers = rows;
-
-        let triggered_rows = rho.tick_rows();
+        let triggered_rows = rho.tick_rows(0);
         assert_eq!(triggered_rows, vec![0, 1, 2, 3]);
 
-        let notes = rho.on_clock_high();
-        assert_eq!(notes.len(), 4);
+        // rows only step on the start of a step
+        assert!(rho.tick_rows(1).is_empty());
+
+        let events = rho.on_tick(PULSES_PER_STEP as u64, Duration::ZERO);
+        assert_eq!(note_ons(&events).len(), 4);
 
         let playing_steps = rho.get_playing_steps();
         assert_eq!(playing_steps, [Some(1), Some(1), Some(1), Some(1)]);
     }
+
+    #[test]
+    fn test_row_rates() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        rho.note_on(62, 100);
+        rho.set_row_activations(vec![vec![true; 4], vec![true; 4], vec![], vec![]]);
+
+        rho.set_row_rate(0, RowRate::new(2, 1));
+        rho.set_row_rate(1, RowRate::new(1, 2));
+
+        // count the note ons for each note over two master steps
+        let mut count = [0, 0];
+        for tick in 0..(PULSES_PER_STEP * 2) as u64 {
+            for note in note_ons(&rho.on_tick(tick, Duration::ZERO)) {
+                count[(note.note_number - 60) / 2] += 1;
+            }
+        }
+        assert_eq!(count, [4, 1]);
+        assert_eq!(rho.get_playing_steps()[0], Some(3));
+        assert_eq!(rho.get_playing_steps()[1], Some(0));
+    }
+
+    #[test]
+    fn test_notes_stop_half_way_through_the_row_step() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        rho.set_row_activations(vec![vec![true; 4], vec![], vec![], vec![]]);
+        rho.set_row_rate(0, RowRate::new(1, 2));
+
+        assert_eq!(note_ons(&rho.on_tick(0, Duration::ZERO)).len(), 1);
+        let half_step = PULSES_PER_STEP as u64;
+        assert!(note_offs(&rho.on_tick(half_step - 1, Duration::ZERO)).is_empty());
+        assert_eq!(note_offs(&rho.on_tick(half_step, Duration::ZERO)).len(), 1);
+        assert!(note_offs(&rho.on_tick(half_step + 1, Duration::ZERO)).is_empty());
+    }
+
+    #[test]
+    fn test_gate_lengths() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        rho.note_on(62, 100);
+        rho.set_row_activations(vec![vec![true; 4], vec![true; 4], vec![], vec![]]);
+
+        // row 0 uses the global gate, row 1 has its own in milliseconds
+        rho.set_gate(GateLength::Percent(25.0));
+        rho.set_row_gate(1, Some(GateLength::Millis(100.0)));
+
+        assert_eq!(note_ons(&rho.on_tick(0, Duration::ZERO)).len(), 2);
+        assert_eq!(rho.next_note_off_time(), Some(Duration::from_millis(100)));
+
+        // the millisecond gate ends between ticks
+        assert!(rho.notes_to_stop(Duration::from_millis(99)).is_empty());
+        let stopped = note_offs(&rho.notes_to_stop(Duration::from_millis(100)));
+        assert_eq!(stopped[0].note_number, 62);
+        assert_eq!(rho.next_note_off_time(), None);
+
+        let quarter_step = PULSES_PER_STEP as u64 / 4;
+        let events = rho.on_tick(quarter_step, Duration::from_millis(110));
+        assert_eq!(note_offs(&events)[0].note_number, 60);
+    }
+
+    #[test]
+    fn test_legato() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        rho.set_row_activations(vec![vec![true, true, false], vec![], vec![], vec![]]);
+        rho.set_row_legato(0, true);
+        let step = PULSES_PER_STEP as u64;
+
+        assert_eq!(note_ons(&rho.on_tick(0, Duration::ZERO)).len(), 1);
+        // held through the gate
+        assert!(rho.on_tick(step / 2, Duration::ZERO).is_empty());
+        // the same note on the next step is tied rather than played again
+        assert!(rho.on_tick(step, Duration::ZERO).is_empty());
+        // the following step is off, so the gate ends the note
+        assert_eq!(
+            note_offs(&rho.on_tick(step * 3 / 2, Duration::ZERO)).len(),
+            1
+        );
+        assert!(rho.on_tick(step * 2, Duration::ZERO).is_empty());
+
+        // with the other rows off, both notes take turns on row 0 and overlap
+        rho.note_on(64, 100);
+        rho.set_row_activations(vec![vec![true, true], vec![], vec![], vec![]]);
+        (1..rho.num_rows()).for_each(|row| rho.note_assigner.set_row_active(row, false));
+        rho.reset();
+        rho.on_tick(step * 3, Duration::ZERO);
+        let events = rho.on_tick(step * 4, Duration::ZERO);
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], NoteEvent::On { row: 0, .. }));
+        assert!(matches!(events[1], NoteEvent::Off { row: 0, .. }));
+    }
+
+    #[test]
+    fn test_row_velocities() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 80);
+        rho.note_on(62, 80);
+        rho.note_on(64, 80);
+        rho.set_row_activations(vec![vec![true], vec![true], vec![true], vec![]]);
+        rho.set_row_velocity(1, VelocityMode::Fixed(127));
+        rho.set_row_velocity(2, VelocityMode::Scaled(50.0));
+
+        let velocities: Vec<(usize, u8)> = rho
+            .on_tick(0, Duration::ZERO)
+            .iter()
+            .filter_map(|event| match event {
+                NoteEvent::On { row, velocity, .. } => Some((*row, *velocity)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(velocities, vec![(0, 80), (1, 127), (2, 40)]);
+
+        // the step's level scales the row's velocity
+        rho.set_row_activations(vec![vec![true, true], vec![], vec![], vec![]]);
+        rho.set_row_levels(vec![vec![1.0, 0.5], vec![], vec![], vec![]]);
+        let step = PULSES_PER_STEP as u64;
+        let velocities: Vec<u8> = rho
+            .on_tick(step, Duration::ZERO)
+            .iter()
+            .filter_map(|event| match event {
+                NoteEvent::On { velocity, .. } => Some(*velocity),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(velocities, vec![40]);
+    }
+
+    #[test]
+    fn test_row_mute() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        rho.note_on(62, 100);
+        rho.set_row_activations(vec![vec![true], vec![true], vec![], vec![]]);
+        rho.set_row_muted(0, true);
+
+        let events = rho.on_tick(0, Duration::ZERO);
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0], NoteEvent::On { row: 1, .. }));
+        // muted rows keep their place
+        assert_eq!(rho.get_playing_steps()[0], Some(0));
+    }
+
+    #[test]
+    fn test_active_rows() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        rho.note_on(62, 100);
+        let note_numbers = |rho: &Rho| -> Vec<Vec<usize>> {
+            rho.get_notes_for_rows()
+                .iter()
+                .map(|notes| notes.iter().map(|note| note.note_number).collect())
+                .collect()
+        };
+
+        // every row takes notes as usual
+        rho.set_row_activations(vec![vec![true], vec![false], vec![true, false], vec![]]);
+        assert_eq!(note_numbers(&rho), vec![vec![60], vec![62], vec![], vec![]]);
+
+        // inverting flips the notes over the rows with a step on
+        rho.set_invert_rows_enabled(true);
+        assert_eq!(note_numbers(&rho), vec![vec![62], vec![], vec![60], vec![]]);
+        let playing: Vec<bool> = rho
+            .get_playing_steps()
+            .iter()
+            .map(Option::is_some)
+            .collect();
+        assert_eq!(playing, vec![true, false, true, false]);
+
+        // only the active rows are filled
+        rho.set_invert_rows_enabled(false);
+        rho.set_row_activations(vec![vec![true], vec![false], vec![true], vec![true]]);
+        rho.note_off(62);
+        rho.set_fill_octaves_enabled(true);
+        assert_eq!(
+            note_numbers(&rho),
+            vec![vec![60], vec![], vec![72], vec![84]]
+        );
+
+        // muted rows keep their notes
+        rho.set_row_muted(2, true);
+        assert_eq!(
+            note_numbers(&rho),
+            vec![vec![60], vec![], vec![72], vec![84]]
+        );
+        rho.set_row_activations(vec![vec![true], vec![false], vec![false], vec![true]]);
+        assert_eq!(note_numbers(&rho), vec![vec![60], vec![], vec![], vec![72]]);
+    }
+
+    #[test]
+    fn test_pinned_notes_stay_when_a_row_is_emptied() {
+        let mut rho = Rho::new();
+        rho.set_row_assign(RowAssign::Hold);
+        rho.set_row_activations(vec![vec![true]; 4]);
+        rho.note_on(60, 100);
+        rho.note_on(62, 100);
+        rho.note_on(64, 100);
+        let before = rho.get_notes_for_rows();
+
+        // clearing a row by hand or with the density doesn't move the held notes
+        rho.set_row_activations(vec![vec![true], vec![false], vec![true], vec![]]);
+        assert_eq!(rho.get_notes_for_rows(), before);
+        rho.set_row_activations(vec![vec![]; 4]);
+        assert_eq!(rho.get_notes_for_rows(), before);
+    }
+
+    #[test]
+    fn test_cc_lanes() {
+        let mut rho = Rho::new();
+        rho.set_row_activations(vec![vec![false, true], vec![true], vec![], vec![]]);
+        let mut lane = CcLane::new();
+        lane.enabled = true;
+        lane.values = vec![10, 20];
+        rho.set_cc_lane(0, lane.clone());
+        let step = PULSES_PER_STEP as u64;
+
+        // lanes step with their row whether or not the step plays a note
+        rho.on_tick(0, Duration::ZERO);
+        let events = rho.cc_events(0);
+        assert_eq!(
+            events,
+            vec![CcEvent {
+                row: 0,
+                channel: None,
+                cc: lane.cc,
+                value: 10
+            }]
+        );
+        rho.on_tick(step / 2, Duration::ZERO);
+        assert!(rho.cc_events(step / 2).is_empty());
+        rho.on_tick(step, Duration::ZERO);
+        assert_eq!(rho.cc_events(step)[0].value, 20);
+
+        // smoothing sends the values on the way to the next step
+        lane.smoothing = true;
+        rho.set_cc_lane(0, lane);
+        rho.on_tick(step * 2, Duration::ZERO);
+        assert_eq!(rho.cc_events(step * 2)[0].value, 10);
+        rho.on_tick(step * 2 + step / 2, Duration::ZERO);
+        assert_eq!(rho.cc_events(step * 2 + step / 2)[0].value, 15);
+    }
+
+    #[test]
+    fn test_set_num_rows() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        rho.note_on(62, 100);
+        rho.set_row_activations(vec![vec![true; 4], vec![true; 4], vec![], vec![]]);
+        rho.set_row_velocity(0, VelocityMode::Fixed(50));
+        assert_eq!(note_ons(&rho.on_tick(0, Duration::ZERO)).len(), 2);
+
+        // the removed row's note is stopped, the row that is left plays on as it was
+        let note_offs = rho.set_num_rows(1);
+        assert_eq!(
+            note_offs,
+            vec![NoteEvent::Off {
+                row: 1,
+                note: Note {
+                    note_number: 62,
+                    velocity: 100
+                }
+            }]
+        );
+        assert_eq!(rho.get_notes_for_rows().len(), 1);
+        let events = rho.on_tick(PULSES_PER_STEP as u64, Duration::ZERO);
+        assert!(matches!(
+            events[..],
+            [
+                NoteEvent::Off { row: 0, .. },
+                NoteEvent::On {
+                    row: 0,
+                    velocity: 50,
+                    ..
+                }
+            ]
+        ));
+
+        // both notes take turns on the one row until more rows are added
+        assert_eq!(rho.get_notes_for_rows()[0].len(), 2);
+        rho.set_num_rows(16);
+        assert_eq!(rho.get_playing_steps().len(), 16);
+        assert_eq!(rho.get_notes_for_rows()[1][0].note_number, 62);
+    }
+
+    #[test]
+    fn test_set_position() {
+        let mut rho = Rho::new();
+        rho.set_row_activations(vec![vec![true; 4], vec![true; 4], vec![], vec![]]);
+        rho.set_row_rate(1, RowRate::new(2, 1));
+
+        // one and a half steps in, row 0 is next on step 2, row 1 on step 3
+        rho.set_position(PULSES_PER_STEP as u64 * 3 / 2);
+        rho.on_tick(PULSES_PER_STEP as u64 * 2, Duration::ZERO);
+        assert_eq!(rho.get_playing_steps()[0], Some(2));
+        assert_eq!(rho.get_playing_steps()[1], Some(3));
+    }
 }
@@ -1 +1,16 @@
-pub const NUM_ROWS: usize = 4;
+// the number of rows can be changed while playing
+pub const DEFAULT_NUM_ROWS: usize = 4;
+pub const MIN_NUM_ROWS: usize = 1;
+pub const MAX_NUM_ROWS: usize = 16;
+
+// midi clock runs at 24 pulses per quarter note, a step is one beat
+pub const MIDI_CLOCK_PPQN: usize = 24;
+pub const STEPS_PER_BEAT: usize = 1;
+pub const PULSES_PER_STEP: usize = MIDI_CLOCK_PPQN / STEPS_PER_BEAT;
+
+// used to time tempo ramps
+pub const BEATS_PER_BAR: usize = 4;
+
+// the number of steps a row can have
+pub const MIN_ROW_LENGTH: usize = 2;
+pub const MAX_ROW_LENGTH: usize = 8;
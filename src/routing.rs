@@ -0,0 +1,103 @@
+// where each row's notes are sent. A row can go to several destinations to layer sounds,
+// by default it goes to the main output port and channel
+
+use crate::rho_config::DEFAULT_NUM_ROWS;
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub struct RowDestination {
+    // None uses the main output port
+    pub port: Option<String>,
+    // None uses the main output channel
+    pub channel: Option<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Routing {
+    pub main_port: Option<String>,
+    pub main_channel: u8,
+    pub rows: Vec<Vec<RowDestination>>,
+}
+
+impl Routing {
+    pub fn new() -> Self {
+        Routing {
+            main_port: None,
+            main_channel: 0,
+            rows: vec![vec![RowDestination::default()]; DEFAULT_NUM_ROWS],
+        }
+    }
+
+    // new rows go to the main output
+    pub fn set_num_rows(&mut self, num_rows: usize) {
+        self.rows.resize(num_rows, vec![RowDestination::default()]);
+    }
+
+    // the ports and channels the row plays on, without repeats
+    pub fn targets(&self, row: usize) -> Vec<(String, u8)> {
+        let mut targets = vec![];
+        for destination in self.rows.get(row).into_iter().flatten() {
+            let port = destination.port.as_ref().or(self.main_port.as_ref());
+            let channel = destination.channel.unwrap_or(self.main_channel);
+            if let Some(port) = port {
+                let target = (port.clone(), channel);
+                if !targets.contains(&target) {
+                    targets.push(target);
+                }
+            }
+        }
+        targets
+    }
+
+    // every port that something is sent to
+    pub fn ports(&self) -> BTreeSet<String> {
+        self.main_port
+            .iter()
+            .chain(self.rows.iter().flatten().filter_map(|d| d.port.as_ref()))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Default for Routing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targets() {
+        let mut routing = Routing::new();
+
+        // nowhere to send to until there is a main port
+        assert!(routing.targets(0).is_empty());
+
+        routing.main_port = Some("Main".to_string());
+        routing.main_channel = 2;
+        assert_eq!(routing.targets(0), vec![("Main".to_string(), 2)]);
+
+        // layer row 1 on another synth, the main destination twice is only played once
+        routing.rows[1] = vec![
+            RowDestination::default(),
+            RowDestination {
+                port: Some("Bass".to_string()),
+                channel: Some(0),
+            },
+            RowDestination {
+                port: None,
+                channel: Some(2),
+            },
+        ];
+        assert_eq!(
+            routing.targets(1),
+            vec![("Main".to_string(), 2), ("Bass".to_string(), 0)]
+        );
+
+        let ports: Vec<String> = routing.ports().into_iter().collect();
+        assert_eq!(ports, vec!["Bass".to_string(), "Main".to_string()]);
+    }
+}
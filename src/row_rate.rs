@@ -0,0 +1,82 @@
+// how fast a row steps relative to the master clock
+
+use crate::rho_config::PULSES_PER_STEP;
+use std::fmt;
+
+// the row takes `steps` steps in the time of `per` master steps
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RowRate {
+    pub steps: usize,
+    pub per: usize,
+}
+
+impl RowRate {
+    pub const fn new(steps: usize, per: usize) -> Self {
+        RowRate { steps, per }
+    }
+
+    // how many clock ticks each step of the row lasts
+    pub fn ticks_per_step(&self) -> usize {
+        (PULSES_PER_STEP * self.per / self.steps.max(1)).max(1)
+    }
+}
+
+impl Default for RowRate {
+    fn default() -> Self {
+        RowRate::new(1, 1)
+    }
+}
+
+impl fmt::Display for RowRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.steps, self.per) {
+            (3, 2) => write!(f, "Triplet"),
+            (s, 1) => write!(f, "{}x", s),
+            (s, p) => write!(f, "{}/{}", s, p),
+        }
+    }
+}
+
+// the rates offered in the gui, all of these divide the clock ticks exactly
+pub const ROW_RATES: [RowRate; 10] = [
+    RowRate::new(1, 4),
+    RowRate::new(1, 3),
+    RowRate::new(1, 2),
+    RowRate::new(2, 3),
+    RowRate::new(3, 4),
+    RowRate::new(1, 1),
+    RowRate::new(3, 2),
+    RowRate::new(2, 1),
+    RowRate::new(3, 1),
+    RowRate::new(4, 1),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticks_per_step() {
+        assert_eq!(RowRate::new(1, 1).ticks_per_step(), PULSES_PER_STEP);
+        assert_eq!(RowRate::new(1, 2).ticks_per_step(), PULSES_PER_STEP * 2);
+        assert_eq!(RowRate::new(2, 1).ticks_per_step(), PULSES_PER_STEP / 2);
+        // triplets fit three steps into two
+        assert_eq!(RowRate::new(3, 2).ticks_per_step() * 3, PULSES_PER_STEP * 2);
+
+        for rate in ROW_RATES {
+            assert_eq!(
+                rate.ticks_per_step() * rate.steps,
+                PULSES_PER_STEP * rate.per,
+                "{} doesn't divide the clock",
+                rate
+            );
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(RowRate::new(1, 1).to_string(), "1x");
+        assert_eq!(RowRate::new(3, 4).to_string(), "3/4");
+        assert_eq!(RowRate::new(3, 2).to_string(), "Triplet");
+    }
+}
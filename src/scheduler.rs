@@ -0,0 +1,426 @@
+// works out when each clock event is due from the tempo and the pulse count.
+// Times are durations since the clock thread started, so the tests can use a virtual clock.
+// Each event time is calculated from an origin rather than by adding up periods, so rounding
+// errors don't accumulate however long we play for.
+//
+// There are two streams of events on the same timebase. Pulses are midi clock, always straight.
+// Ticks are the same resolution but have swing applied, and drive the steps.
+
+use crate::rho_config::{MIDI_CLOCK_PPQN, PULSES_PER_STEP};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClockEvent {
+    // a midi clock pulse
+    Pulse,
+    // a tick of the step clock, index counts ticks since the last reset
+    Tick { index: u64 },
+}
+
+// swing is the fraction of a pair of steps taken by the first step
+pub const MIN_SWING: f32 = 0.5;
+pub const MAX_SWING: f32 = 0.75;
+
+// a linear change of tempo over a number of pulses
+#[derive(Debug, Clone, Copy)]
+struct Ramp {
+    from_tempo: f32,
+    to_tempo: f32,
+    start_position: f64,
+    length: f64,
+}
+
+pub struct Scheduler {
+    tempo: f32,
+    pulse_period_ns: f64,
+    ramp: Option<Ramp>,
+    swing: f32,
+    // the time at which origin_position is due, event times are measured from here.
+    // positions are measured in straight pulses
+    origin: Duration,
+    origin_position: f64,
+    last_position: f64,
+    next_pulse: u64,
+    next_tick: u64,
+}
+
+impl Scheduler {
+    pub fn new(tempo: f32) -> Self {
+        Scheduler {
+            tempo,
+            pulse_period_ns: pulse_period_ns(tempo),
+            ramp: None,
+            swing: MIN_SWING,
+            origin: Duration::ZERO,
+            origin_position: 0.0,
+            last_position: 0.0,
+            next_pulse: 0,
+            next_tick: 0,
+        }
+    }
+
+    // the new tempo takes effect from the last event, so the next one moves smoothly.
+    // cancels any ramp
+    pub fn set_tempo(&mut self, tempo: f32) {
+        self.ramp = None;
+        self.change_tempo(tempo);
+    }
+
+    pub fn tempo(&self) -> f32 {
+        self.tempo
+    }
+
+    // change tempo gradually over this many pulses, the tempo moves a little on every pulse
+    pub fn ramp_tempo(&mut self, tempo: f32, pulses: u64) {
+        if pulses == 0 {
+            self.set_tempo(tempo);
+            return;
+        }
+        self.ramp = Some(Ramp {
+            from_tempo: self.tempo,
+            to_tempo: tempo,
+            start_position: self.last_position.max(self.origin_position),
+            length: pulses as f64,
+        });
+    }
+
+    // the pulses played since the last reset, which is where we are in the song
+    pub fn pulse_count(&self) -> u64 {
+        self.next_pulse
+    }
+
+    pub fn is_ramping(&self) -> bool {
+        self.ramp.is_some()
+    }
+
+    // go straight to the end of a ramp, for when there are no pulses to move it along
+    pub fn finish_ramp(&mut self) {
+        if let Some(ramp) = self.ramp {
+            self.set_tempo(ramp.to_tempo);
+        }
+    }
+
+    pub fn set_swing(&mut self, swing: f32) {
+        self.swing = swing.clamp(MIN_SWING, MAX_SWING);
+    }
+
+    // start counting from the first pulse, which is due now
+    pub fn reset(&mut self, now: Duration) {
+        self.origin = now;
+        self.origin_position = 0.0;
+        self.last_position = 0.0;
+        self.next_pulse = 0;
+        self.next_tick = 0;
+    }
+
+    // carry on counting from where we stopped, the next event is due now
+    pub fn resume(&mut self, now: Duration) {
+        self.origin = now;
+        self.origin_position = self.next_pulse_position().min(self.next_tick_position());
+    }
+
+    pub fn next_event_time(&self) -> Duration {
+        self.time_at(self.next_pulse_position().min(self.next_tick_position()))
+    }
+
+    // returns the next event if it is due, call repeatedly to catch up if we are late
+    pub fn poll(&mut self, now: Duration) -> Option<ClockEvent> {
+        let pulse_position = self.next_pulse_position();
+        let tick_position = self.next_tick_position();
+
+        if pulse_position <= tick_position {
+            if now < self.time_at(pulse_position) {
+                return None;
+            }
+            self.next_pulse += 1;
+            self.last_position = pulse_position;
+            self.update_ramp();
+            Some(ClockEvent::Pulse)
+        } else {
+            if now < self.time_at(tick_position) {
+                return None;
+            }
+            let index = self.next_tick;
+            self.next_tick += 1;
+            self.last_position = tick_position;
+            Some(ClockEvent::Tick { index })
+        }
+    }
+
+    fn change_tempo(&mut self, tempo: f32) {
+        // after a resume nothing has happened since the origin
+        let position = self.last_position.max(self.origin_position);
+        self.origin = self.time_at(position);
+        self.origin_position = position;
+        self.tempo = tempo;
+        self.pulse_period_ns = pulse_period_ns(tempo);
+    }
+
+    // move the tempo along the ramp to where it should be at the last pulse
+    fn update_ramp(&mut self) {
+        let Some(ramp) = self.ramp else {
+            return;
+        };
+        let progress = ((self.last_position - ramp.start_position) / ramp.length).clamp(0.0, 1.0);
+        let tempo = ramp.from_tempo + (ramp.to_tempo - ramp.from_tempo) * progress as f32;
+        self.change_tempo(tempo);
+        if progress >= 1.0 {
+            self.ramp = None;
+        }
+    }
+
+    fn next_pulse_position(&self) -> f64 {
+        self.next_pulse as f64
+    }
+
+    fn next_tick_position(&self) -> f64 {
+        swing_position(self.next_tick as f64, self.swing)
+    }
+
+    fn time_at(&self, position: f64) -> Duration {
+        let pulses_since_origin = (position - self.origin_position).max(0.0);
+        let ns = (pulses_since_origin * self.pulse_period_ns).round() as u64;
+        self.origin + Duration::from_nanos(ns)
+    }
+}
+
+fn pulse_period_ns(tempo: f32) -> f64 {
+    let beats_per_second = tempo.max(1.0) as f64 / 60.0;
+    1e9 / (beats_per_second * MIDI_CLOCK_PPQN as f64)
+}
+
+// Stretches the first step of each pair and squashes the second, so the offbeat step is late.
+// This warps time rather than delaying whole steps, so anything on the offbeat of the
+// master clock moves together, whatever row it is in.
+fn swing_position(position: f64, swing: f32) -> f64 {
+    let step = PULSES_PER_STEP as f64;
+    let pair = 2.0 * step;
+    let pair_start = (position / pair).floor() * pair;
+    // position within the pair, in steps 0..2
+    let p = (position - pair_start) / step;
+    let first_len = 2.0 * swing as f64;
+    let swung = if p < 1.0 {
+        p * first_len
+    } else {
+        first_len + (p - 1.0) * (2.0 - first_len)
+    };
+    pair_start + swung * step
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // run the scheduler against a virtual clock, returning the times the events were emitted
+    fn run_until(scheduler: &mut Scheduler, end: Duration) -> Vec<(Duration, ClockEvent)> {
+        let mut events = vec![];
+        loop {
+            let now = scheduler.next_event_time();
+            if now > end {
+                break;
+            }
+            while let Some(event) = scheduler.poll(now) {
+                events.push((now, event));
+            }
+        }
+        events
+    }
+
+    // the times of the starts and the middles of the steps, true for the start
+    fn edges(events: &[(Duration, ClockEvent)]) -> Vec<(Duration, bool)> {
+        let step = PULSES_PER_STEP as u64;
+        events
+            .iter()
+            .filter_map(|(t, e)| match e {
+                ClockEvent::Tick { index } if index % step == 0 => Some((*t, true)),
+                ClockEvent::Tick { index } if index % step == step / 2 => Some((*t, false)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn pulse_times(events: &[(Duration, ClockEvent)]) -> Vec<Duration> {
+        events
+            .iter()
+            .filter(|(_, e)| *e == ClockEvent::Pulse)
+            .map(|(t, _)| *t)
+            .collect()
+    }
+
+    fn nanos(ns: f64) -> Duration {
+        Duration::from_nanos(ns.round() as u64)
+    }
+
+    #[test]
+    fn test_pulse_times() {
+        // 60 bpm is one beat a second
+        let mut scheduler = Scheduler::new(60.0);
+        scheduler.reset(Duration::ZERO);
+
+        assert_eq!(scheduler.poll(Duration::ZERO), Some(ClockEvent::Pulse));
+        assert_eq!(
+            scheduler.poll(Duration::ZERO),
+            Some(ClockEvent::Tick { index: 0 })
+        );
+        // not due yet
+        assert_eq!(scheduler.poll(Duration::from_millis(1)), None);
+
+        let period = nanos(1e9 / MIDI_CLOCK_PPQN as f64);
+        assert_eq!(scheduler.next_event_time(), period);
+        assert_eq!(scheduler.poll(period), Some(ClockEvent::Pulse));
+    }
+
+    #[test]
+    fn test_step_edges() {
+        let mut scheduler = Scheduler::new(120.0);
+        scheduler.reset(Duration::ZERO);
+
+        let events = run_until(&mut scheduler, Duration::from_secs(1));
+        let edges = edges(&events);
+
+        // two steps a second, high then low half way through
+        assert_eq!(edges[0], (Duration::ZERO, true));
+        assert_eq!(edges[1], (Duration::from_millis(250), false));
+        assert_eq!(edges[2], (Duration::from_millis(500), true));
+        assert_eq!(edges[3], (Duration::from_millis(750), false));
+    }
+
+    #[test]
+    fn test_swing() {
+        // one step a second
+        let mut scheduler = Scheduler::new(60.0);
+        scheduler.set_swing(0.75);
+        scheduler.reset(Duration::ZERO);
+
+        let events = run_until(&mut scheduler, Duration::from_millis(2500));
+        let edges = edges(&events);
+
+        // the first step takes 3/4 of the pair, the second is late and short
+        assert_eq!(edges[0], (Duration::ZERO, true));
+        assert_eq!(edges[1], (Duration::from_millis(750), false));
+        assert_eq!(edges[2], (Duration::from_millis(1500), true));
+        assert_eq!(edges[3], (Duration::from_millis(1750), false));
+        // and the next pair starts on time
+        assert_eq!(edges[4], (Duration::from_millis(2000), true));
+
+        // midi clock stays straight
+        let pulses = pulse_times(&events);
+        assert_eq!(pulses[MIDI_CLOCK_PPQN], Duration::from_secs(1));
+        assert_eq!(pulses[2 * MIDI_CLOCK_PPQN], Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_no_drift() {
+        // 137 bpm doesn't divide into a whole number of nanoseconds per pulse
+        let tempo = 137.0;
+        let mut scheduler = Scheduler::new(tempo);
+        scheduler.reset(Duration::ZERO);
+
+        let hour = Duration::from_secs(60 * 60);
+        let pulses = pulse_times(&run_until(&mut scheduler, hour));
+
+        let last_pulse = pulses.len() - 1;
+        let expected_secs = last_pulse as f64 * 60.0 / (tempo as f64 * MIDI_CLOCK_PPQN as f64);
+        let error = (pulses[last_pulse].as_secs_f64() - expected_secs).abs();
+        assert!(error < 1e-6, "drifted by {} seconds", error);
+    }
+
+    #[test]
+    fn test_late_poll_catches_up() {
+        let mut scheduler = Scheduler::new(60.0);
+        scheduler.reset(Duration::ZERO);
+
+        // 100ms late is a few pulses and ticks, they all come out straight away
+        let mut count = 0;
+        while scheduler.poll(Duration::from_millis(100)).is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 6);
+
+        // the late poll doesn't shift the following events
+        let period_ns = 1e9 / MIDI_CLOCK_PPQN as f64;
+        assert_eq!(scheduler.next_event_time(), nanos(3.0 * period_ns));
+    }
+
+    #[test]
+    fn test_tempo_change_and_resume() {
+        let mut scheduler = Scheduler::new(60.0);
+        scheduler.reset(Duration::ZERO);
+        let period_ns = 1e9 / MIDI_CLOCK_PPQN as f64;
+
+        while scheduler.poll(nanos(period_ns)).is_some() {}
+
+        // doubling the tempo halves the gap from the last event
+        scheduler.set_tempo(120.0);
+        assert_eq!(scheduler.next_event_time(), nanos(1.5 * period_ns));
+
+        // stop for a while, then resume, the count carries on
+        scheduler.resume(Duration::from_secs(10));
+        assert_eq!(scheduler.next_event_time(), Duration::from_secs(10));
+        assert_eq!(
+            scheduler.poll(Duration::from_secs(10)),
+            Some(ClockEvent::Pulse)
+        );
+        assert_eq!(
+            scheduler.poll(Duration::from_secs(10)),
+            Some(ClockEvent::Tick { index: 2 })
+        );
+    }
+
+    #[test]
+    fn test_tempo_ramp() {
+        let mut scheduler = Scheduler::new(60.0);
+        scheduler.reset(Duration::ZERO);
+        // the first pulse is at the start of the ramp
+        scheduler.poll(Duration::ZERO);
+
+        // double the tempo over one beat
+        scheduler.ramp_tempo(120.0, MIDI_CLOCK_PPQN as u64);
+        assert!(scheduler.is_ramping());
+
+        let events = run_until(&mut scheduler, Duration::from_secs(2));
+        let pulses = pulse_times(&events);
+
+        // the gaps between pulses shrink smoothly
+        let gaps: Vec<Duration> = pulses.windows(2).map(|w| w[1] - w[0]).collect();
+        assert!(gaps[..MIDI_CLOCK_PPQN].windows(2).all(|w| w[1] < w[0]));
+
+        // then stay at the new tempo
+        assert!(!scheduler.is_ramping());
+        assert_eq!(scheduler.tempo(), 120.0);
+        let period = nanos(0.5e9 / MIDI_CLOCK_PPQN as f64);
+        let last_gap = gaps[gaps.len() - 1];
+        assert!((last_gap.as_secs_f64() - period.as_secs_f64()).abs() < 1e-6);
+
+        // the ramp took less than a beat at the old tempo and more than one at the new
+        let ramp_time = pulses[MIDI_CLOCK_PPQN];
+        assert!(ramp_time < Duration::from_secs(1));
+        assert!(ramp_time > Duration::from_millis(500));
+
+        // setting the tempo directly cancels a ramp
+        scheduler.ramp_tempo(60.0, MIDI_CLOCK_PPQN as u64);
+        scheduler.set_tempo(90.0);
+        assert!(!scheduler.is_ramping());
+    }
+
+    #[test]
+    fn test_finish_ramp() {
+        let mut scheduler = Scheduler::new(60.0);
+        scheduler.reset(Duration::ZERO);
+        scheduler.poll(Duration::ZERO);
+        scheduler.ramp_tempo(120.0, MIDI_CLOCK_PPQN as u64);
+        let events = run_until(&mut scheduler, Duration::from_millis(300));
+        assert!(scheduler.is_ramping());
+        assert!(scheduler.tempo() > 60.0 && scheduler.tempo() < 120.0);
+
+        // stopped part way, the ramp jumps to its end
+        scheduler.finish_ramp();
+        assert!(!scheduler.is_ramping());
+        assert_eq!(scheduler.tempo(), 120.0);
+
+        // and playing on is at the new tempo from the last pulse
+        let last_pulse = pulse_times(&events).last().copied().unwrap();
+        let period = nanos(0.5e9 / MIDI_CLOCK_PPQN as f64);
+        assert_eq!(scheduler.next_event_time() - last_pulse, period);
+    }
+}
@@ -1,7 +1,15 @@
 use eframe::egui;
 use egui::Color32;
 
-pub fn step_switch_ui(ui: &mut egui::Ui, on: &mut bool, is_playing: bool) -> egui::Response {
+// how bright an active step at the lowest level is, compared to one at full level
+const MIN_LEVEL_BRIGHTNESS: f32 = 0.3;
+
+pub fn step_switch_ui(
+    ui: &mut egui::Ui,
+    on: &mut bool,
+    level: &mut f32,
+    is_playing: bool,
+) -> egui::Response {
     let desired_height = ui.spacing().interact_size.y * 2.0;
     // use all available width
     let desired_width = ui.available_width();
@@ -9,21 +17,29 @@ pub fn step_switch_ui(ui: &mut egui::Ui, on: &mut bool, is_playing: bool) -> egu
 
     // 2. Allocating space:
     // This is where we get a region of the screen assigned.
-    // We also tell the Ui to sense clicks in the allocated region.
-    let (rect, mut response) = ui.allocate_exact_size(desired_size, egui::Sense::click());
+    // We also tell the Ui to sense clicks and drags in the allocated region.
+    let (rect, mut response) = ui.allocate_exact_size(desired_size, egui::Sense::click_and_drag());
 
     if response.clicked() {
         *on = !*on;
         response.mark_changed(); // report back that the value changed
     }
 
+    // dragging the full height of the switch goes from silent to full level
+    if response.dragged() && response.drag_delta().y != 0.0 {
+        *level = (*level - response.drag_delta().y / rect.height()).clamp(0.0, 1.0);
+        response.mark_changed();
+    }
+
     // Attach some meta-data to the response which can be used by screen readers:
     response.widget_info(|| egui::WidgetInfo::selected(egui::WidgetType::Checkbox, *on, ""));
 
     // 4. Paint!
     // Make sure we need to paint:
     if ui.is_rect_visible(rect) {
-        let how_on = ui.ctx().animate_bool(response.id, *on);
+        // active steps are brighter the higher their level
+        let brightness = MIN_LEVEL_BRIGHTNESS + (1.0 - MIN_LEVEL_BRIGHTNESS) * *level;
+        let how_on = ui.ctx().animate_bool(response.id, *on) * brightness;
         let visuals = ui.style().interact_selectable(&response, *on);
 
         // All coordinates are in absolute screen coordinates so we use `rect` to place the elements.
@@ -67,8 +83,12 @@ pub fn step_switch_ui(ui: &mut egui::Ui, on: &mut bool, is_playing: bool) -> egu
 /// ``` ignore
 /// ui.add(toggle(&mut my_bool));
 /// ```
-pub fn step_switch(on: &mut bool, is_playing: bool) -> impl egui::Widget + '_ {
-    move |ui: &mut egui::Ui| step_switch_ui(ui, on, is_playing)
+pub fn step_switch<'a>(
+    on: &'a mut bool,
+    level: &'a mut f32,
+    is_playing: bool,
+) -> impl egui::Widget + 'a {
+    move |ui: &mut egui::Ui| step_switch_ui(ui, on, level, is_playing)
 }
 
 pub fn url_to_file_source_code() -> String {
@@ -0,0 +1,79 @@
+// works out a tempo from taps on a button or key
+
+use std::collections::VecDeque;
+
+// a gap longer than this starts a new set of taps
+const MAX_TAP_INTERVAL_SECS: f64 = 2.0;
+// how many of the most recent intervals are averaged
+const NUM_INTERVALS_TO_AVERAGE: usize = 4;
+
+pub struct TapTempo {
+    last_tap: Option<f64>,
+    intervals: VecDeque<f64>,
+}
+
+impl TapTempo {
+    pub fn new() -> Self {
+        TapTempo {
+            last_tap: None,
+            intervals: VecDeque::with_capacity(NUM_INTERVALS_TO_AVERAGE),
+        }
+    }
+
+    // call on each tap with the time in seconds, returns the tempo once there are two taps
+    pub fn tap(&mut self, time: f64) -> Option<f32> {
+        if let Some(last) = self.last_tap {
+            let interval = time - last;
+            if interval > MAX_TAP_INTERVAL_SECS || interval <= 0.0 {
+                self.intervals.clear();
+            } else {
+                if self.intervals.len() == NUM_INTERVALS_TO_AVERAGE {
+                    self.intervals.pop_front();
+                }
+                self.intervals.push_back(interval);
+            }
+        }
+        self.last_tap = Some(time);
+        self.bpm()
+    }
+
+    fn bpm(&self) -> Option<f32> {
+        if self.intervals.is_empty() {
+            return None;
+        }
+        let mean_interval = self.intervals.iter().sum::<f64>() / self.intervals.len() as f64;
+        Some((60.0 / mean_interval) as f32)
+    }
+}
+
+impl Default for TapTempo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tap_tempo() {
+        let mut tap_tempo = TapTempo::new();
+
+        assert_eq!(tap_tempo.tap(10.0), None);
+        assert_eq!(tap_tempo.tap(10.5), Some(120.0));
+        // the average of the recent taps
+        assert_eq!(tap_tempo.tap(11.5), Some(80.0));
+
+        // only the last few taps count
+        let mut bpm = None;
+        for i in 1..=NUM_INTERVALS_TO_AVERAGE {
+            bpm = tap_tempo.tap(11.5 + i as f64 * 0.25);
+        }
+        assert_eq!(bpm, Some(240.0));
+
+        // a long gap starts again
+        assert_eq!(tap_tempo.tap(20.0), None);
+        assert_eq!(tap_tempo.tap(21.0), Some(60.0));
+    }
+}
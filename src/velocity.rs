@@ -0,0 +1,113 @@
+// how hard the notes we send are played
+
+use std::fmt;
+
+pub const MAX_VELOCITY: u8 = 127;
+
+// where a row's velocity comes from
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum VelocityMode {
+    // the velocity the note was played with
+    #[default]
+    Played,
+    Fixed(u8),
+    // the played velocity, as a percentage
+    Scaled(f32),
+}
+
+// shapes the played velocities, soft makes quiet notes louder and hard makes them quieter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VelocityCurve {
+    #[default]
+    Linear,
+    Soft,
+    Hard,
+}
+
+pub const VELOCITY_CURVES: [VelocityCurve; 3] = [
+    VelocityCurve::Linear,
+    VelocityCurve::Soft,
+    VelocityCurve::Hard,
+];
+
+impl VelocityMode {
+    // the velocity to send for a note played with this velocity, never 0 as that is a note off
+    pub fn velocity(&self, played: u8, curve: VelocityCurve) -> u8 {
+        let velocity = match *self {
+            VelocityMode::Played => curve.apply(played) as f32,
+            VelocityMode::Fixed(velocity) => velocity as f32,
+            VelocityMode::Scaled(percent) => curve.apply(played) as f32 * percent / 100.0,
+        };
+        velocity.round().clamp(1.0, MAX_VELOCITY as f32) as u8
+    }
+}
+
+// scale a velocity by a step's level, never down to a note off
+pub fn apply_level(velocity: u8, level: f32) -> u8 {
+    (velocity as f32 * level)
+        .round()
+        .clamp(1.0, MAX_VELOCITY as f32) as u8
+}
+
+impl fmt::Display for VelocityMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VelocityMode::Played => write!(f, "Played"),
+            VelocityMode::Fixed(velocity) => write!(f, "{}", velocity),
+            VelocityMode::Scaled(percent) => write!(f, "{:.0}%", percent),
+        }
+    }
+}
+
+impl VelocityCurve {
+    pub fn apply(&self, velocity: u8) -> u8 {
+        let exponent = match self {
+            VelocityCurve::Linear => return velocity,
+            VelocityCurve::Soft => 0.5,
+            VelocityCurve::Hard => 2.0,
+        };
+        let x = velocity.min(MAX_VELOCITY) as f32 / MAX_VELOCITY as f32;
+        (x.powf(exponent) * MAX_VELOCITY as f32).round() as u8
+    }
+}
+
+impl fmt::Display for VelocityCurve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VelocityCurve::Linear => write!(f, "Linear"),
+            VelocityCurve::Soft => write!(f, "Soft"),
+            VelocityCurve::Hard => write!(f, "Hard"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_velocity() {
+        let linear = VelocityCurve::Linear;
+        assert_eq!(VelocityMode::Played.velocity(90, linear), 90);
+        assert_eq!(VelocityMode::Fixed(64).velocity(90, linear), 64);
+        assert_eq!(VelocityMode::Scaled(50.0).velocity(90, linear), 45);
+        // kept in range, and never a note off
+        assert_eq!(VelocityMode::Scaled(200.0).velocity(90, linear), 127);
+        assert_eq!(VelocityMode::Scaled(0.0).velocity(90, linear), 1);
+
+        // the curve shapes what was played, the ends stay where they are
+        assert_eq!(VelocityCurve::Soft.apply(127), 127);
+        assert_eq!(VelocityCurve::Hard.apply(0), 0);
+        assert!(VelocityCurve::Soft.apply(64) > 64);
+        assert!(VelocityCurve::Hard.apply(64) < 64);
+        assert_eq!(
+            VelocityMode::Played.velocity(64, VelocityCurve::Hard),
+            VelocityCurve::Hard.apply(64)
+        );
+        // a fixed velocity isn't played, so isn't curved
+        assert_eq!(
+            VelocityMode::Fixed(64).velocity(90, VelocityCurve::Hard),
+            64
+        );
+    }
+}
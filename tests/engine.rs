@@ -0,0 +1,465 @@
+// drives the clock thread end to end with the mock midi backend on a virtual clock, checking what
+// it sends
+
+use rho_eframe::clock_runner::run_clock;
+use rho_eframe::engine_clock::{VirtualClock, VirtualClockHandle};
+use rho_eframe::messages::*;
+use rho_eframe::midi_learn::{CcSource, Control};
+use rho_eframe::midi_thru::MidiThru;
+use rho_eframe::mock_midi::{MockMidi, SentMessage};
+use rho_eframe::port_watcher::MidiPorts;
+use rho_eframe::rho_config::{DEFAULT_NUM_ROWS, MIDI_CLOCK_PPQN};
+use rho_eframe::routing::RowDestination;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const IN_PORT: &str = "Keys";
+const OUT_PORT: &str = "Synth";
+// 60ms steps
+const TEMPO: f32 = 1000.0;
+
+struct Engine {
+    mock: MockMidi,
+    tx: Sender<MessageGuiToRho>,
+    // what the engine tells the gui
+    rx: Receiver<MessageToGui>,
+    // the engine only moves on when we move its clock
+    clock: VirtualClockHandle,
+    running: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl Engine {
+    // an engine listening to the keys and playing the synth, with the first step of the first row
+    // active
+    fn start() -> Self {
+        let mock = MockMidi::new();
+        let (tx_to_gui, rx) = channel();
+        let (tx, rx_from_gui) = channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let (virtual_clock, clock) = VirtualClock::new();
+        let handle = run_clock(
+            mock.clone(),
+            virtual_clock,
+            tx_to_gui,
+            running.clone(),
+            rx_from_gui,
+        );
+
+        let mut engine = Engine {
+            mock,
+            tx,
+            rx,
+            clock,
+            running,
+            handle,
+        };
+        engine.send(MessageGuiToRho::PortsChanged {
+            ports: MidiPorts {
+                inputs: vec![IN_PORT.to_string()],
+                outputs: vec![OUT_PORT.to_string()],
+            },
+        });
+        engine.send(MessageGuiToRho::SetMidiInPort {
+            port: Some(IN_PORT.to_string()),
+        });
+        engine.send(MessageGuiToRho::SetMidiOutPort {
+            port: Some(OUT_PORT.to_string()),
+        });
+        engine.send(MessageGuiToRho::SetTempo { tempo: TEMPO });
+        let mut row_activations = vec![vec![false]; DEFAULT_NUM_ROWS];
+        row_activations[0] = vec![true];
+        engine.send(MessageGuiToRho::RowActivations {
+            row_activations,
+            row_levels: vec![],
+        });
+        engine.run(0);
+        engine
+    }
+
+    fn send(&self, message: MessageGuiToRho) {
+        self.tx.send(message).unwrap();
+    }
+
+    // let the engine handle what it has been sent, and play for a while
+    fn run(&mut self, ms: u64) {
+        self.clock.run_for(Duration::from_millis(ms));
+    }
+
+    fn receive(&mut self, bytes: &[u8]) {
+        assert!(self.mock.receive(IN_PORT, bytes));
+        self.run(0);
+    }
+
+    fn play_note(&mut self, note: u8) {
+        self.receive(&[NOTE_ON_MSG, note, 90]);
+    }
+
+    // the bytes sent to the synth since the last time we looked
+    fn take_sent(&self) -> Vec<Vec<u8>> {
+        let bytes = self.mock.sent_to(OUT_PORT);
+        self.mock.clear();
+        bytes
+    }
+
+    // stop the thread and return everything it sent
+    fn stop(self) -> Vec<SentMessage> {
+        self.running.store(false, Ordering::SeqCst);
+        // lets the engine out of its wait
+        drop(self.clock);
+        self.handle.join().unwrap();
+        self.mock.sent()
+    }
+}
+
+#[test]
+fn test_notes_play_and_stop() {
+    let mut engine = Engine::start();
+    engine.send(MessageGuiToRho::SetMidiChannelOut { channel: 2 });
+    engine.play_note(60);
+
+    // every step plays the note on the out channel as hard as it was played, and the gate stops
+    // it half way through the step
+    let note_on = vec![NOTE_ON_MSG + 2, 60, 90];
+    let note_off = vec![NOTE_OFF_MSG + 2, 60, NOTE_OFF_VELOCITY];
+    engine.send(MessageGuiToRho::SetPlaying { playing: true });
+    engine.run(0);
+    assert_eq!(engine.take_sent(), vec![note_on.clone()]);
+    engine.run(29);
+    assert!(engine.take_sent().is_empty());
+    engine.run(1);
+    assert_eq!(engine.take_sent(), vec![note_off]);
+    engine.run(29);
+    assert!(engine.take_sent().is_empty());
+    engine.run(1);
+    assert_eq!(engine.take_sent(), vec![note_on]);
+
+    // nothing is left sounding after stopping
+    engine.run(10);
+    engine.send(MessageGuiToRho::SetPlaying { playing: false });
+    engine.run(0);
+    assert_eq!(
+        engine.take_sent(),
+        vec![vec![NOTE_OFF_MSG + 2, 60, NOTE_OFF_VELOCITY]]
+    );
+    engine.run(100);
+    assert!(engine.stop().is_empty());
+}
+
+#[test]
+fn test_clock_out() {
+    let mut engine = Engine::start();
+    engine.send(MessageGuiToRho::SetMidiClockOut {
+        port: OUT_PORT.to_string(),
+        enabled: true,
+    });
+    engine.run(0);
+
+    engine.send(MessageGuiToRho::SetPlaying { playing: true });
+    engine.run(0);
+    // 24 a step, the first one as we start
+    engine.run(59);
+    engine.send(MessageGuiToRho::SetPlaying { playing: false });
+    engine.run(0);
+    let bytes = engine.take_sent();
+    engine.stop();
+
+    // no notes are held, so there is only the clock between start and stop
+    let mut expected = vec![vec![START_MSG]];
+    expected.extend(vec![vec![TIMING_CLOCK_MSG]; 24]);
+    expected.push(vec![STOP_MSG]);
+    assert_eq!(bytes, expected);
+}
+
+#[test]
+fn test_panic() {
+    let mut engine = Engine::start();
+    engine.send(MessageGuiToRho::Panic);
+    engine.run(0);
+    let bytes = engine.take_sent();
+    engine.stop();
+
+    let expected: Vec<Vec<u8>> = (0..16)
+        .map(|channel| vec![CONTROL_CHANGE_MSG + channel, ALL_NOTES_OFF_CC, 0])
+        .collect();
+    assert_eq!(bytes, expected);
+}
+
+#[test]
+fn test_midi_learn() {
+    let mut engine = Engine::start();
+    engine.send(MessageGuiToRho::LearnControl {
+        control: Some(Control::Play),
+    });
+    engine.run(0);
+
+    // the first controller moved is learned rather than acted on
+    engine.receive(&[CONTROL_CHANGE_MSG + 1, 20, 127]);
+    assert!(engine.rx.try_iter().any(|message| matches!(
+        message,
+        MessageToGui::Learned {
+            source: CcSource { channel: 1, cc: 20 },
+            control: Control::Play,
+        }
+    )));
+
+    // then pressing it plays and pressing it again stops, and the gui hears about it. Moving
+    // further up or coming back down isn't a press
+    for value in [100, 110, 0, 127] {
+        engine.receive(&[CONTROL_CHANGE_MSG + 1, 20, value]);
+    }
+    let toggles: Vec<bool> = engine
+        .rx
+        .try_iter()
+        .filter_map(|message| match message {
+            MessageToGui::Playing { playing } => Some(playing),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(toggles, vec![true, false]);
+    engine.stop();
+}
+
+#[test]
+fn test_midi_thru() {
+    let mut engine = Engine::start();
+    engine.send(MessageGuiToRho::SetMidiChannelOut { channel: 2 });
+    engine.send(MessageGuiToRho::SetMidiThru {
+        thru: MidiThru {
+            enabled: true,
+            ..MidiThru::new()
+        },
+    });
+    engine.run(0);
+
+    // bends and pressure are passed on the out channel, the notes are ours to play
+    engine.receive(&[PITCH_BEND_MSG, 0x00, 0x50]);
+    engine.receive(&[CHANNEL_PRESSURE_MSG, 80]);
+    engine.play_note(60);
+    let bytes = engine.take_sent();
+    engine.stop();
+
+    assert_eq!(
+        bytes,
+        vec![
+            vec![PITCH_BEND_MSG + 2, 0x00, 0x50],
+            vec![CHANNEL_PRESSURE_MSG + 2, 80]
+        ]
+    );
+}
+
+#[test]
+fn test_held_notes_released_on_channel_change() {
+    let mut engine = Engine::start();
+    engine.play_note(60);
+    // its note off now never gets past the channel filter
+    engine.send(MessageGuiToRho::SetMidiChannelIn { channel: Some(1) });
+    engine.run(0);
+
+    engine.send(MessageGuiToRho::SetPlaying { playing: true });
+    engine.run(200);
+    engine.send(MessageGuiToRho::SetPlaying { playing: false });
+    engine.run(0);
+
+    assert!(engine.stop().is_empty());
+}
+
+#[test]
+fn test_tempo_ramp_while_stopped() {
+    let mut engine = Engine::start();
+    engine.rx.try_iter().for_each(drop);
+
+    // there are no pulses to ramp over, so the gui is told the new tempo straight away
+    engine.send(MessageGuiToRho::RampTempo {
+        tempo: 120.0,
+        bars: 4.0,
+    });
+    engine.run(0);
+    let tempos: Vec<f32> = engine
+        .rx
+        .try_iter()
+        .filter_map(|message| match message {
+            MessageToGui::Tempo { tempo } => Some(tempo),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(tempos, vec![120.0]);
+    engine.stop();
+}
+
+#[test]
+fn test_clock_source_switch() {
+    let mut engine = Engine::start();
+    engine.play_note(60);
+    engine.send(MessageGuiToRho::SetPlaying { playing: true });
+    engine.run(10);
+    assert_eq!(engine.take_sent(), vec![vec![NOTE_ON_MSG, 60, 90]]);
+
+    // the sounding note is stopped, and nothing plays without an incoming clock
+    engine.send(MessageGuiToRho::SetClockSource {
+        source: ClockSource::ExternalMidi,
+    });
+    engine.run(500);
+    assert_eq!(
+        engine.take_sent(),
+        vec![vec![NOTE_OFF_MSG, 60, NOTE_OFF_VELOCITY]]
+    );
+
+    // coming back carries on from where the internal clock stopped rather than playing the
+    // missed steps at once. It stopped after the pulse at 10ms, 19 pulses of 2.5ms before the
+    // next step
+    engine.send(MessageGuiToRho::SetClockSource {
+        source: ClockSource::Internal,
+    });
+    engine.run(47);
+    assert!(engine.take_sent().is_empty());
+    engine.run(1);
+    assert_eq!(engine.take_sent(), vec![vec![NOTE_ON_MSG, 60, 90]]);
+    engine.stop();
+}
+
+#[test]
+fn test_external_tempo_cleared() {
+    let mut engine = Engine::start();
+    engine.send(MessageGuiToRho::SetClockSource {
+        source: ClockSource::ExternalMidi,
+    });
+    engine.receive(&[START_MSG]);
+    // a beat of pulses at 120 bpm
+    let interval_us = 1_000_000 / 48;
+    for i in 0..=MIDI_CLOCK_PPQN as u64 {
+        assert!(engine
+            .mock
+            .receive_at(IN_PORT, i * interval_us, &[TIMING_CLOCK_MSG]));
+    }
+    engine.run(0);
+    // then the pulses stop, so the gui stops showing the tempo
+    engine.run(600);
+
+    let bpms: Vec<Option<f32>> = engine
+        .rx
+        .try_iter()
+        .filter_map(|message| match message {
+            MessageToGui::ExternalTempo { bpm } => Some(bpm.map(f32::round)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(bpms, vec![None, Some(120.0), None]);
+
+    // and when the master stops
+    engine.receive(&[TIMING_CLOCK_MSG]);
+    engine.receive(&[STOP_MSG]);
+    assert!(engine
+        .rx
+        .try_iter()
+        .any(|message| matches!(message, MessageToGui::ExternalTempo { bpm: None })));
+    engine.stop();
+}
+
+#[test]
+fn test_clock_out_enabled_while_playing() {
+    let mut engine = Engine::start();
+    engine.send(MessageGuiToRho::SetPlaying { playing: true });
+    engine.run(30);
+    engine.send(MessageGuiToRho::SetMidiClockOut {
+        port: OUT_PORT.to_string(),
+        enabled: true,
+    });
+
+    // it waits for the next sixteenth, the pulse at 45ms, then is told to carry on from there
+    engine.run(14);
+    assert!(engine.take_sent().is_empty());
+    engine.run(1);
+    assert_eq!(
+        engine.take_sent(),
+        vec![
+            vec![SONG_POSITION_MSG, 3, 0],
+            vec![CONTINUE_MSG],
+            vec![TIMING_CLOCK_MSG]
+        ]
+    );
+    engine.run(5);
+    assert_eq!(engine.take_sent(), vec![vec![TIMING_CLOCK_MSG]; 2]);
+    engine.stop();
+}
+
+#[test]
+fn test_row_destinations_changed_while_playing() {
+    let mut engine = Engine::start();
+    engine.play_note(60);
+    engine.send(MessageGuiToRho::SetPlaying { playing: true });
+    engine.run(10);
+    assert_eq!(engine.take_sent(), vec![vec![NOTE_ON_MSG, 60, 90]]);
+
+    // adding a channel leaves the note sounding where it is
+    let main = RowDestination::default();
+    let channel_3 = RowDestination {
+        port: None,
+        channel: Some(3),
+    };
+    engine.send(MessageGuiToRho::SetRowDestinations {
+        row: 0,
+        destinations: vec![main, channel_3.clone()],
+    });
+    engine.run(0);
+    assert!(engine.take_sent().is_empty());
+
+    // only the channel it no longer goes to is stopped
+    engine.send(MessageGuiToRho::SetRowDestinations {
+        row: 0,
+        destinations: vec![channel_3],
+    });
+    engine.run(0);
+    assert_eq!(
+        engine.take_sent(),
+        vec![vec![NOTE_OFF_MSG, 60, NOTE_OFF_VELOCITY]]
+    );
+    engine.run(20);
+    assert_eq!(
+        engine.take_sent(),
+        vec![vec![NOTE_OFF_MSG + 3, 60, NOTE_OFF_VELOCITY]]
+    );
+}
+
+#[test]
+fn test_thru_notes_left_to_the_player() {
+    let mut engine = Engine::start();
+    engine.send(MessageGuiToRho::SetMidiChannelOut { channel: 2 });
+    engine.send(MessageGuiToRho::SetMidiThru {
+        thru: MidiThru {
+            enabled: true,
+            notes: true,
+            rechannelize: false,
+            ..MidiThru::new()
+        },
+    });
+    engine.run(0);
+    engine.play_note(60);
+    assert_eq!(engine.take_sent(), vec![vec![NOTE_ON_MSG, 60, 90]]);
+    engine.send(MessageGuiToRho::SetPlaying { playing: true });
+    engine.run(10);
+    engine.take_sent();
+
+    // stopping and panic only stop the sequencer's notes, the key is still down
+    engine.send(MessageGuiToRho::SetPlaying { playing: false });
+    engine.run(0);
+    assert_eq!(
+        engine.take_sent(),
+        vec![vec![NOTE_OFF_MSG + 2, 60, NOTE_OFF_VELOCITY]]
+    );
+    engine.send(MessageGuiToRho::Panic);
+    engine.run(0);
+    assert!(!engine
+        .take_sent()
+        .contains(&vec![NOTE_OFF_MSG, 60, NOTE_OFF_VELOCITY]));
+
+    // letting go of the key stops it
+    engine.receive(&[NOTE_OFF_MSG, 60, 0]);
+    assert_eq!(
+        engine.take_sent(),
+        vec![vec![NOTE_OFF_MSG, 60, NOTE_OFF_VELOCITY]]
+    );
+}